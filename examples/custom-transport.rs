@@ -12,7 +12,8 @@ use anyhow::{anyhow, Result};
 use parking_lot::{Condvar, Mutex};
 use reqwest::Client;
 use sentry::{
-    Dsn, Event, Options, RawEnvelope, Request, Transport as SentryTransport, TransportShutdown,
+    Dsn, Event, Options, RateLimiter, RawEnvelope, Request, Transport as SentryTransport,
+    TransportShutdown,
 };
 use sentry_contrib_native as sentry;
 use std::{convert::TryInto, str::FromStr, sync::Arc, time::Duration};
@@ -22,7 +23,11 @@ use tokio::{
 };
 
 /// Send the request.
-async fn send_sentry_request(client: &Client, request: Request) -> Result<()> {
+async fn send_sentry_request(
+    client: &Client,
+    limiter: &mut RateLimiter,
+    request: Request,
+) -> Result<()> {
     let request = request.map(|body| body.as_bytes().to_vec());
 
     let response = client
@@ -30,6 +35,9 @@ async fn send_sentry_request(client: &Client, request: Request) -> Result<()> {
         .await
         .map_err(|e| anyhow!("Failed to send Sentry request: {}", e))?;
 
+    // respect rate limits sent by the Sentry service
+    limiter.update_from_headers(response.status().as_u16(), response.headers());
+
     response
         .error_for_status()
         .map_err(|e| anyhow!("Received error response from Sentry: {}", e))?;
@@ -62,12 +70,23 @@ impl Transport {
         let dsn = Dsn::from_str(options.dsn().expect("no DSN found")).expect("invalid DSN");
 
         tokio::spawn(async move {
+            let mut limiter = RateLimiter::new();
+
             // dequeue and send events until we are asked to shut down
             while let Some(envelope) = receiver.recv().await {
+                // don't send anything that is rate limited
+                let envelope = match limiter.filter(envelope.serialize()) {
+                    Some(envelope) => envelope,
+                    None => {
+                        eprintln!("dropped sentry envelope because of rate limits");
+                        continue;
+                    }
+                };
+
                 // convert the envelope into an HTTP request
-                let req = envelope.to_request(dsn.clone());
+                let req = envelope.into_request(dsn.clone());
 
-                match send_sentry_request(&client, req).await {
+                match send_sentry_request(&client, &mut limiter, req).await {
                     Ok(_) => eprintln!("successfully sent sentry envelope"),
                     Err(err) => eprintln!("failed to send sentry envelope: {}", err),
                 }
@@ -397,6 +397,10 @@ extern "C" {
     #[link_name = "sentry_uuid_nil"]
     pub fn uuid_nil() -> Uuid;
 
+    /// Creates a new uuid4.
+    #[link_name = "sentry_uuid_new_v4"]
+    pub fn uuid_new_v4() -> Uuid;
+
     /// Formats the uuid into a string buffer.
     #[link_name = "sentry_uuid_as_string"]
     pub fn uuid_as_string(uuid: *const Uuid, str: *mut c_char);
@@ -0,0 +1,279 @@
+//! Conversion of Rust backtraces into Sentry stacktraces.
+
+#[cfg(feature = "symbolicate")]
+use crate::Thread;
+use crate::{Frame, Stacktrace};
+#[cfg(feature = "symbolicate")]
+use ::{
+    backtrace::Backtrace,
+    std::{
+        convert::TryFrom,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Set by [`Options::set_rust_symbolication`](crate::Options::set_rust_symbolication)
+/// for the running SDK.
+#[cfg(feature = "symbolicate")]
+static SYMBOLICATE: AtomicBool = AtomicBool::new(false);
+
+/// Prefixes of functions that don't belong to the application.
+const NOT_IN_APP: &[&str] = &[
+    "std::",
+    "core::",
+    "alloc::",
+    "backtrace::",
+    "anyhow::",
+    "eyre::",
+    "sentry_contrib_native::",
+    "rust_begin_unwind",
+    "__",
+    "_start",
+];
+
+/// Enables or disables in-process symbolication, see [`capture`].
+#[cfg(feature = "symbolicate")]
+pub(crate) fn set_symbolicate(enabled: bool) {
+    SYMBOLICATE.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if in-process symbolication is enabled.
+#[cfg(feature = "symbolicate")]
+pub(crate) fn symbolicate() -> bool {
+    SYMBOLICATE.load(Ordering::Relaxed)
+}
+
+/// Captures and symbolicates the stack of the current thread, returned like
+/// the `threads` interface sentry-native generates. `len` limits the number
+/// of frames, zero captures all. Frames of this crate and the `backtrace`
+/// crate on top of the stack are removed.
+#[cfg(feature = "symbolicate")]
+pub(crate) fn capture(len: usize) -> Option<Thread> {
+    let backtrace = Backtrace::new();
+    let mut frames = Vec::new();
+
+    for raw in backtrace.frames() {
+        let instruction_addr = format!("{:p}", raw.ip());
+
+        // inlined functions share the instruction pointer of their caller
+        for symbol in raw.symbols() {
+            let mut frame = symbol
+                .name()
+                .map_or_else(|| frame("<unknown>"), |name| frame(&format!("{:#}", name)));
+            frame.instruction_addr = Some(instruction_addr.clone());
+            frame.symbol_addr = symbol.addr().map(|addr| format!("{:p}", addr));
+            frame.abs_path = symbol.filename().map(|path| path.display().to_string());
+            frame.filename = frame.abs_path.clone();
+            frame.lineno = symbol.lineno().and_then(|line| i32::try_from(line).ok());
+            frame.colno = symbol.colno().and_then(|column| i32::try_from(column).ok());
+            frames.push(frame);
+        }
+
+        if raw.symbols().is_empty() {
+            let mut frame = frame("<unknown>");
+            frame.instruction_addr = Some(instruction_addr);
+            frames.push(frame);
+        }
+    }
+
+    let internal = frames
+        .iter()
+        .take_while(|frame| frame.function.as_deref().map_or(false, internal))
+        .count();
+    frames.drain(..internal);
+
+    if len != 0 {
+        frames.truncate(len);
+    }
+
+    if frames.is_empty() {
+        return None;
+    }
+
+    // Sentry expects the most recent call last
+    frames.reverse();
+
+    Some(Thread {
+        current: Some(true),
+        stacktrace: Some(Stacktrace {
+            frames: Some(frames),
+            ..Stacktrace::default()
+        }),
+        ..Thread::default()
+    })
+}
+
+/// Parses the [`Display`](std::fmt::Display) output of a
+/// [`std::backtrace::Backtrace`] into a [`Stacktrace`], as captured by
+/// `anyhow` and `eyre` errors. Returns [`None`] if no frame was found.
+#[cfg(any(feature = "integration-anyhow", feature = "integration-eyre"))]
+pub(crate) fn parse(backtrace: &str) -> Option<Stacktrace> {
+    let mut frames = Vec::new();
+
+    for line in backtrace.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut() {
+                locate(frame, location);
+            }
+        } else if let Some((index, function)) = line.split_once(": ") {
+            if !index.is_empty() && index.bytes().all(|byte| byte.is_ascii_digit()) {
+                frames.push(frame(function));
+            }
+        }
+    }
+
+    if frames.is_empty() {
+        None
+    } else {
+        // Sentry expects the most recent call last
+        frames.reverse();
+
+        Some(Stacktrace {
+            frames: Some(frames),
+            ..Stacktrace::default()
+        })
+    }
+}
+
+/// Returns `true` if `function` belongs to this crate or the `backtrace`
+/// crate, including trait implementations like
+/// `<sentry_contrib_native::Type as core::fmt::Debug>::fmt`.
+#[cfg(feature = "symbolicate")]
+fn internal(function: &str) -> bool {
+    let unqualified = function.trim_start_matches('<');
+    unqualified.starts_with("backtrace::") || unqualified.starts_with("sentry_contrib_native::")
+}
+
+/// Creates a [`Frame`] for the demangled `function`.
+fn frame(function: &str) -> Frame {
+    let unqualified = function.trim_start_matches('<');
+    let in_app = function != "<unknown>"
+        && !NOT_IN_APP
+            .iter()
+            .any(|prefix| unqualified.starts_with(prefix));
+
+    Frame {
+        function: Some(function.to_owned()),
+        in_app: Some(in_app),
+        ..Frame::default()
+    }
+}
+
+/// Sets the file, line and column of `frame` from `location`, formatted as
+/// `path:line:column`.
+#[cfg(any(feature = "integration-anyhow", feature = "integration-eyre"))]
+fn locate(frame: &mut Frame, location: &str) {
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next().and_then(|column| column.parse().ok());
+    let line = parts.next().and_then(|line| line.parse().ok());
+
+    let (path, line, column) = match (line, column, parts.next()) {
+        (Some(line), Some(column), Some(path)) => (path, Some(line), Some(column)),
+        // no column, the path might contain a colon itself
+        _ => match location.rsplit_once(':') {
+            Some((path, line)) if line.parse::<i32>().is_ok() => (path, line.parse().ok(), None),
+            _ => (location, None, None),
+        },
+    };
+
+    frame.abs_path = Some(path.to_owned());
+    frame.filename = Some(path.to_owned());
+    frame.lineno = line;
+    frame.colno = column;
+}
+
+#[cfg(any(feature = "integration-anyhow", feature = "integration-eyre"))]
+#[test]
+fn backtrace() {
+    let backtrace = "   0: anyhow::error::<impl anyhow::Error>::msg\n\
+        \x20            at /cargo/anyhow-1.0.0/src/error.rs:83:36\n\
+        \x20  1: app::config::load\n\
+        \x20            at ./src/config.rs:12:5\n\
+        \x20  2: <unknown>\n\
+        \x20  3: main\n\
+        \x20            at C:\\app\\src\\main.rs:3\n";
+
+    let frames = parse(backtrace).unwrap().frames.unwrap();
+    assert_eq!(4, frames.len());
+
+    assert_eq!(Some("main"), frames[0].function.as_deref());
+    assert_eq!(Some("C:\\app\\src\\main.rs"), frames[0].abs_path.as_deref());
+    assert_eq!(Some(3), frames[0].lineno);
+    assert_eq!(None, frames[0].colno);
+    assert_eq!(Some(true), frames[0].in_app);
+
+    assert_eq!(Some("<unknown>"), frames[1].function.as_deref());
+    assert_eq!(None, frames[1].abs_path);
+    assert_eq!(Some(false), frames[1].in_app);
+
+    assert_eq!(Some("app::config::load"), frames[2].function.as_deref());
+    assert_eq!(Some("./src/config.rs"), frames[2].filename.as_deref());
+    assert_eq!(Some(12), frames[2].lineno);
+    assert_eq!(Some(5), frames[2].colno);
+    assert_eq!(Some(true), frames[2].in_app);
+
+    assert_eq!(Some(false), frames[3].in_app);
+
+    assert_eq!(None, parse("disabled backtrace"));
+}
+
+#[cfg(feature = "symbolicate")]
+#[test]
+fn symbolicated() {
+    let frames = capture(0).unwrap().stacktrace.unwrap().frames.unwrap();
+
+    // the innermost frame is the one that called into this crate
+    let frame = frames.last().unwrap();
+    assert_ne!(None, frame.instruction_addr);
+    assert!(frames.iter().any(|frame| frame.lineno.is_some()));
+    assert!(frames
+        .iter()
+        .filter_map(|frame| frame.function.as_deref())
+        .all(|function| !function.starts_with("backtrace::")));
+
+    assert_eq!(
+        2,
+        capture(2)
+            .unwrap()
+            .stacktrace
+            .unwrap()
+            .frames
+            .unwrap()
+            .len()
+    );
+}
+
+#[cfg(feature = "symbolicate")]
+#[test]
+fn internal_frames() {
+    /// Captures the stack from inside a trait implementation.
+    struct Captured(Option<Thread>);
+
+    impl Default for Captured {
+        fn default() -> Self {
+            Self(capture(0))
+        }
+    }
+
+    assert!(internal("backtrace::backtrace::trace"));
+    assert!(internal("sentry_contrib_native::backtrace::capture"));
+    assert!(internal(
+        "<sentry_contrib_native::event::Event as core::clone::Clone>::clone"
+    ));
+    assert!(!internal(
+        "<app::Config as core::default::Default>::default"
+    ));
+    assert!(!internal("main"));
+
+    let frames = Captured::default()
+        .0
+        .unwrap()
+        .stacktrace
+        .unwrap()
+        .frames
+        .unwrap();
+    assert!(frames
+        .iter()
+        .filter_map(|frame| frame.function.as_deref())
+        .all(|function| !function.starts_with("<sentry_contrib_native::")));
+}
@@ -1,6 +1,6 @@
 //! Implementation details for [`Options::set_before_send`].
 
-use crate::{ffi, Value};
+use crate::{ffi, statistics, DropReason, Value};
 #[cfg(doc)]
 use crate::{Event, Options};
 use once_cell::sync::Lazy;
@@ -85,9 +85,14 @@ pub extern "C" fn before_send(
     let before_send = ManuallyDrop::new(unsafe { Box::from_raw(before_send) });
 
     ffi::catch(|| {
-        before_send
-            .before_send(unsafe { Value::from_raw(event) })
-            .into_raw()
+        let event = before_send.before_send(unsafe { Value::from_raw(event) });
+
+        // sentry-native discards the event
+        if event.is_null() {
+            statistics().record_dropped(DropReason::BeforeSend);
+        }
+
+        event.into_raw()
     })
 }
 
@@ -0,0 +1,329 @@
+//! Capturing of Rust errors.
+
+use crate::{Event, Exception, Uuid};
+use std::{
+    any,
+    char::ParseCharError,
+    env::VarError,
+    error::Error,
+    fmt, io, iter,
+    net::AddrParseError,
+    num::{ParseFloatError, ParseIntError, TryFromIntError},
+    str::{ParseBoolError, Utf8Error},
+    string::FromUtf8Error,
+    time::SystemTimeError,
+};
+
+/// Captures `error` and its chain of [`Error::source`]s as a new event, see
+/// [`Event::from_error`].
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::capture_error;
+/// if let Err(error) = "test".parse::<i32>() {
+///     capture_error(&error);
+/// }
+/// ```
+#[allow(clippy::must_use_candidate)]
+pub fn capture_error<E: Error + ?Sized>(error: &E) -> Uuid {
+    Event::from_error(error).capture()
+}
+
+/// Captures an [`anyhow::Error`] as a new event, see [`Event::from_anyhow`].
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::capture_anyhow;
+/// capture_anyhow(&anyhow::anyhow!("failed to connect"));
+/// ```
+#[cfg(feature = "integration-anyhow")]
+#[allow(clippy::must_use_candidate)]
+pub fn capture_anyhow(error: &anyhow::Error) -> Uuid {
+    Event::from_anyhow(error).capture()
+}
+
+/// Captures an [`eyre::Report`] as a new event, see [`Event::from_eyre`].
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::capture_eyre;
+/// capture_eyre(&eyre::eyre!("failed to connect"));
+/// ```
+#[cfg(feature = "integration-eyre")]
+#[allow(clippy::must_use_candidate)]
+pub fn capture_eyre(report: &eyre::Report) -> Uuid {
+    Event::from_eyre(report).capture()
+}
+
+/// Extension trait to capture the error of a [`Result`].
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::ResultExt;
+/// let number = "test".parse::<i32>().capture_err().unwrap_or_default();
+/// ```
+pub trait ResultExt {
+    /// Captures the error with [`capture_error`] if this is [`Err`] and
+    /// returns `self` unchanged.
+    #[must_use]
+    fn capture_err(self) -> Self;
+}
+
+impl<T, E: Error> ResultExt for Result<T, E> {
+    fn capture_err(self) -> Self {
+        if let Err(error) = &self {
+            capture_error(error);
+        }
+
+        self
+    }
+}
+
+/// Converts `error` and its chain of [`Error::source`]s into exceptions,
+/// ordered from the innermost source to `error`, as Sentry expects them.
+pub(crate) fn exceptions<E: Error + ?Sized>(error: &E) -> Vec<Exception> {
+    let mut exceptions = chain_exceptions(iter::successors(error.source(), |error| error.source()));
+
+    let type_name = any::type_name::<E>();
+    // `dyn Error` doesn't tell us anything about the concrete type
+    exceptions.push(if type_name.starts_with("dyn ") {
+        exception(error, None)
+    } else {
+        exception(error, Some(type_name))
+    });
+
+    exceptions
+}
+
+/// Converts a chain of errors, starting with the outermost, into exceptions,
+/// ordered from the innermost to the outermost, as Sentry expects them.
+pub(crate) fn chain_exceptions<'a, I: IntoIterator<Item = &'a (dyn Error + 'static)>>(
+    chain: I,
+) -> Vec<Exception> {
+    let mut exceptions: Vec<_> = chain
+        .into_iter()
+        .map(|error| exception(error, std_type_name(error)))
+        .collect();
+    exceptions.reverse();
+    exceptions
+}
+
+/// Yields the path of `error` if it's one of the common error types of the
+/// standard library, which is otherwise unknown for a `dyn Error`.
+fn std_type_name(error: &(dyn Error + 'static)) -> Option<&'static str> {
+    macro_rules! type_name {
+        ($($ty:ty => $name:literal),* $(,)?) => {
+            $(
+                if error.is::<$ty>() {
+                    return Some($name);
+                }
+            )*
+        };
+    }
+
+    type_name!(
+        io::Error => "std::io::Error",
+        ParseIntError => "std::num::ParseIntError",
+        ParseFloatError => "std::num::ParseFloatError",
+        TryFromIntError => "std::num::TryFromIntError",
+        Utf8Error => "std::str::Utf8Error",
+        FromUtf8Error => "std::string::FromUtf8Error",
+        ParseBoolError => "std::str::ParseBoolError",
+        ParseCharError => "std::char::ParseCharError",
+        AddrParseError => "std::net::AddrParseError",
+        VarError => "std::env::VarError",
+        SystemTimeError => "std::time::SystemTimeError",
+        fmt::Error => "std::fmt::Error",
+    );
+
+    None
+}
+
+/// Converts a single `error` into an exception. If `type_name` isn't given,
+/// the type is reported as `Error`.
+fn exception<E: Error + ?Sized>(error: &E, type_name: Option<&str>) -> Exception {
+    let (module, ty) = if let Some(type_name) = type_name {
+        // strip generics, they are too noisy to group by
+        let path = type_name.split('<').next().unwrap_or(type_name);
+
+        match path.rfind("::") {
+            Some(index) => (Some(path[..index].to_owned()), path[index + 2..].to_owned()),
+            None => (None, path.to_owned()),
+        }
+    } else {
+        (None, "Error".into())
+    };
+
+    Exception {
+        ty: Some(ty),
+        value: Some(error.to_string()),
+        module,
+        ..Exception::default()
+    }
+}
+
+#[test]
+fn error() {
+    use std::{
+        convert::TryFrom,
+        fmt::{Display, Formatter, Result as FmtResult},
+    };
+
+    #[derive(Debug)]
+    struct Outer(ParseIntError);
+
+    impl Display for Outer {
+        fn fmt(&self, formatter: &mut Formatter<'_>) -> FmtResult {
+            formatter.write_str("failed to parse config")
+        }
+    }
+
+    impl Error for Outer {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Io(io::Error);
+
+    impl Display for Io {
+        fn fmt(&self, formatter: &mut Formatter<'_>) -> FmtResult {
+            formatter.write_str("failed to read config")
+        }
+    }
+
+    impl Error for Io {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    let error = Outer("test".parse::<i32>().unwrap_err());
+    let exceptions = exceptions(&error);
+
+    assert_eq!(2, exceptions.len());
+    assert_eq!(Some("ParseIntError"), exceptions[0].ty.as_deref());
+    assert_eq!(Some("std::num"), exceptions[0].module.as_deref());
+    assert_eq!(
+        Some("invalid digit found in string"),
+        exceptions[0].value.as_deref()
+    );
+    assert_eq!(Some("Outer"), exceptions[1].ty.as_deref());
+    assert!(exceptions[1].module.as_deref().unwrap().ends_with("error"));
+    assert_eq!(
+        Some("failed to parse config"),
+        exceptions[1].value.as_deref()
+    );
+
+    let boxed: Box<dyn Error> = Box::new(error);
+    let exceptions = self::exceptions(&*boxed);
+    assert_eq!(Some("Error"), exceptions[1].ty.as_deref());
+    assert_eq!(None, exceptions[1].module);
+
+    for source in [
+        io::Error::from_raw_os_error(2),
+        io::Error::new(io::ErrorKind::Other, "custom"),
+        io::Error::from(io::ErrorKind::NotFound),
+    ] {
+        let exceptions = self::exceptions(&Io(source));
+        assert_eq!(Some("Error"), exceptions[0].ty.as_deref());
+        assert_eq!(Some("std::io"), exceptions[0].module.as_deref());
+        assert_eq!(Some("Io"), exceptions[1].ty.as_deref());
+    }
+
+    let event = Event::from_error(&*boxed);
+    let values = event
+        .get("exception")
+        .and_then(|exception| exception.as_map()?.get("values")?.as_list())
+        .unwrap();
+    assert_eq!(2, values.len());
+
+    let outer = Exception::try_from(values[1].clone()).unwrap();
+    assert_eq!(
+        Some(true),
+        outer.mechanism.and_then(|mechanism| mechanism.handled)
+    );
+    assert!(outer.stacktrace.is_some());
+    assert_eq!(
+        None,
+        Exception::try_from(values[0].clone()).unwrap().mechanism
+    );
+
+    event.capture();
+}
+
+#[cfg(feature = "integration-anyhow")]
+#[test]
+fn anyhow() {
+    use anyhow::Context;
+    use std::convert::TryFrom;
+
+    let error = "test"
+        .parse::<i32>()
+        .context("failed to parse config")
+        .unwrap_err();
+    let event = Event::from_anyhow(&error);
+    let values = event
+        .get("exception")
+        .and_then(|exception| exception.as_map()?.get("values")?.as_list())
+        .unwrap();
+    assert_eq!(2, values.len());
+
+    let inner = Exception::try_from(values[0].clone()).unwrap();
+    assert_eq!(Some("ParseIntError"), inner.ty.as_deref());
+
+    let outer = Exception::try_from(values[1].clone()).unwrap();
+    assert_eq!(Some("failed to parse config"), outer.value.as_deref());
+    assert!(outer.mechanism.is_some());
+    assert!(outer.stacktrace.is_some());
+
+    event.capture();
+}
+
+#[cfg(all(test, feature = "integration-eyre"))]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn eyre_handler() -> anyhow::Result<()> {
+    use eyre::{EyreHandler, WrapErr};
+    use std::{
+        convert::TryFrom,
+        fmt::{Formatter, Result},
+    };
+
+    /// Handler printing a backtrace that wasn't captured.
+    struct Handler;
+
+    impl EyreHandler for Handler {
+        fn debug(&self, error: &(dyn Error + 'static), fmt: &mut Formatter<'_>) -> Result {
+            write!(
+                fmt,
+                "{}\n\nStack backtrace:\n   0: custom::frame\n             at src/custom.rs:1:1",
+                error
+            )
+        }
+    }
+
+    eyre::set_hook(Box::new(|_| Box::new(Handler)))?;
+
+    let report = "test"
+        .parse::<i32>()
+        .wrap_err("failed to parse config")
+        .unwrap_err();
+    assert!(format!("{:?}", report).contains("custom::frame"));
+
+    let event = Event::from_eyre(&report);
+    let values = event
+        .get("exception")
+        .and_then(|exception| exception.as_map()?.get("values")?.as_list())
+        .unwrap();
+    assert_eq!(2, values.len());
+
+    // the output of other handlers isn't parsed, the current thread is used
+    let outer = Exception::try_from(values[1].clone())?;
+    let frames = outer.stacktrace.and_then(|stacktrace| stacktrace.frames);
+    assert!(frames.map_or(true, |frames| frames
+        .iter()
+        .all(|frame| frame.function.as_deref() != Some("custom::frame"))));
+
+    Ok(())
+}
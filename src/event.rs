@@ -1,9 +1,22 @@
 //! Sentry event implementation.
 
-use crate::{CToR, Level, Map, Object, RToC, Value};
+#[cfg(any(
+    feature = "integration-anyhow",
+    feature = "integration-eyre",
+    feature = "symbolicate"
+))]
+use crate::backtrace;
+use crate::{
+    error, throttle, CToR, Exception, Level, Map, Mechanism, Object, RToC, Stacktrace, Thread,
+    ThrottleKind, Value,
+};
+#[cfg(doc)]
+use crate::{Options, Throttle};
 use std::{
     cmp::Ordering,
     collections::BTreeMap,
+    convert::TryFrom,
+    error::Error,
     ffi::CStr,
     fmt::{Display, Formatter, Result},
     hash::{Hash, Hasher},
@@ -133,8 +146,20 @@ impl Event {
         self.deref_mut().insert(key.into(), value.into());
     }
 
-    /// Generate stacktrace.
+    /// Generate stacktrace, symbolicated in-process if enabled with
+    /// [`Options::set_rust_symbolication`].
     fn stacktrace(len: usize) -> BTreeMap<String, Value> {
+        #[cfg(feature = "symbolicate")]
+        {
+            if backtrace::symbolicate() {
+                if let Some(thread) = backtrace::capture(len) {
+                    let mut threads = BTreeMap::new();
+                    threads.insert("values".into(), vec![thread].into());
+                    return threads;
+                }
+            }
+        }
+
         let event = unsafe {
             let value = sys::value_new_event();
             sys::event_value_add_stacktrace(value, ptr::null_mut(), len);
@@ -181,24 +206,271 @@ impl Event {
     /// event.capture();
     /// ```
     pub fn add_exception<M: Map + Into<Value>>(&mut self, exception: M, len: usize) {
-        let stacktrace = Self::stacktrace(len)
+        let mut exception = exception
+            .into()
+            .into_map()
+            .expect("`Map` isn't `Value::Map`");
+        exception.insert("stacktrace".into(), Self::exception_stacktrace(len));
+        self.insert("exception", exception);
+    }
+
+    /// Generate stacktrace with `len` instruction pointers to be put into an
+    /// exception.
+    pub(crate) fn exception_stacktrace(len: usize) -> Value {
+        Self::stacktrace(len)
             .remove("values")
             .and_then(|values| values.into_list().ok())
             .and_then(|values| values.into_iter().next())
             .and_then(|thread| thread.into_map().ok())
             .and_then(|mut thread| thread.remove("stacktrace"))
             .filter(Value::is_map)
-            .expect("failed to move stacktrace");
+            .expect("failed to move stacktrace")
+    }
 
-        let mut exception = exception
-            .into()
+    /// Appends `thread` to the threads of the [`Event`], so that the state of
+    /// several threads can be reported, e.g. for deadlocks or worker pools.
+    /// Threads added with [`Event::add_stacktrace`] are kept.
+    ///
+    /// Numeric thread IDs are passed to Sentry Native, which expects them,
+    /// other IDs are set as they are. [`Exception::thread_id`] can refer to
+    /// them.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Event, Thread};
+    /// let mut event = Event::new();
+    /// event.append_thread(Thread {
+    ///     id: Some("1".into()),
+    ///     name: Some("main".into()),
+    ///     current: Some(true),
+    ///     ..Thread::default()
+    /// });
+    /// event.append_thread(Thread {
+    ///     id: Some("2".into()),
+    ///     name: Some("worker".into()),
+    ///     crashed: Some(true),
+    ///     ..Thread::default()
+    /// });
+    /// event.capture();
+    /// ```
+    pub fn append_thread(&mut self, thread: Thread) {
+        let id: Option<u64> = thread.id.as_deref().and_then(|id| id.parse().ok());
+        let name = thread.name.clone().map(RToC::into_cstring);
+        let name = name.as_deref().map_or(ptr::null(), CStr::as_ptr);
+
+        let raw = unsafe { sys::value_new_thread(id.unwrap_or_default(), name) };
+        let raw = if id.is_some() {
+            // keep the ID as Sentry Native set it
+            Self::merge_raw(raw, Thread { id: None, ..thread }.into(), &["name"])
+        } else {
+            Self::merge_raw(raw, thread.into(), &["id", "name"])
+        };
+        self.append("threads", raw, sys::event_add_thread);
+    }
+
+    /// Appends `exception` to the exceptions of the [`Event`], ordered from
+    /// the innermost to the outermost, as Sentry expects them. An exception
+    /// added with [`Event::add_exception`] is kept.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Event, Exception};
+    /// let mut event = Event::new();
+    /// event.append_exception(Exception {
+    ///     ty: Some("ConnectionRefused".into()),
+    ///     value: Some("worker 2 failed to connect".into()),
+    ///     thread_id: Some("2".into()),
+    ///     ..Exception::default()
+    /// });
+    /// event.append_exception(Exception {
+    ///     ty: Some("Timeout".into()),
+    ///     value: Some("workers didn't finish in time".into()),
+    ///     thread_id: Some("1".into()),
+    ///     ..Exception::default()
+    /// });
+    /// event.capture();
+    /// ```
+    pub fn append_exception(&mut self, exception: Exception) {
+        let ty = exception.ty.clone().unwrap_or_default().into_cstring();
+        let value = exception.value.clone().unwrap_or_default().into_cstring();
+
+        let raw = unsafe { sys::value_new_exception(ty.as_ptr(), value.as_ptr()) };
+        let raw = Self::merge_raw(raw, exception.into(), &["type", "value"]);
+        self.append("exception", raw, sys::event_add_exception);
+    }
+
+    /// Sets all entries of the map `object` on `raw`. Entries of `defaults`,
+    /// that Sentry Native set on creation, are removed if `object` doesn't
+    /// have them.
+    ///
+    /// # Panics
+    /// Panics if Sentry failed to allocate memory.
+    fn merge_raw(raw: sys::Value, object: Value, defaults: &[&str]) -> sys::Value {
+        let map = object
             .into_map()
-            .expect("`Map` isn't `Value::Map`");
-        exception.insert("stacktrace".into(), stacktrace);
-        self.insert("exception", exception);
+            .expect("protocol types are always `Value::Map`");
+
+        for key in defaults.iter().filter(|key| !map.contains_key(**key)) {
+            let key = (*key).to_owned().into_cstring();
+            unsafe { sys::value_remove_by_key(raw, key.as_ptr()) };
+        }
+
+        for (key, value) in map {
+            let key = key.into_cstring();
+
+            match unsafe { sys::value_set_by_key(raw, key.as_ptr(), value.into_raw()) } {
+                0 => (),
+                _ => panic!("Sentry failed to allocate memory"),
+            }
+        }
+
+        raw
+    }
+
+    /// Appends `item` to the `values` of `key` with `add`, which is either
+    /// [`sys::event_add_thread`] or [`sys::event_add_exception`].
+    ///
+    /// # Panics
+    /// Panics if Sentry failed to allocate memory.
+    fn append(
+        &mut self,
+        key: &str,
+        item: sys::Value,
+        add: unsafe extern "C" fn(sys::Value, sys::Value),
+    ) {
+        let raw = unsafe { sys::value_new_object() };
+
+        if let Some(existing) = self.map.remove(key) {
+            let existing = match existing {
+                Value::List(list) => Value::new(vec![("values", list)]),
+                // a single object, e.g. from `Event::add_exception`, isn't a list
+                Value::Map(map) if !map.contains_key("values") => {
+                    Value::new(vec![("values", vec![Value::Map(map)])])
+                }
+                existing => existing,
+            };
+
+            let key = key.to_owned().into_cstring();
+
+            match unsafe { sys::value_set_by_key(raw, key.as_ptr(), existing.into_raw()) } {
+                0 => (),
+                _ => panic!("Sentry failed to allocate memory"),
+            }
+        }
+
+        unsafe { add(raw, item) };
+
+        if let Some(values) = unsafe { Value::from_raw(raw) }
+            .into_map()
+            .ok()
+            .and_then(|mut event| event.remove(key))
+        {
+            self.insert(key, values);
+        }
+    }
+
+    /// Creates a new Sentry event from `error` and its chain of
+    /// [`Error::source`]s, adding one exception for each of them. The
+    /// exception of `error` itself is marked with a `generic` mechanism and
+    /// gets the stacktrace of the current thread.
+    ///
+    /// The type of an exception is taken from the concrete type of `error`.
+    /// Sources are only available as `dyn Error`, their type is only known for
+    /// common error types of the standard library, like [`std::io::Error`],
+    /// and reported as `Error` otherwise.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Event;
+    /// if let Err(error) = "test".parse::<i32>() {
+    ///     Event::from_error(&error).capture();
+    /// }
+    /// ```
+    #[must_use = "`Event` doesn't do anything without `Event::capture`"]
+    pub fn from_error<E: Error + ?Sized>(error: &E) -> Self {
+        Self::from_exceptions(error::exceptions(error), None)
+    }
+
+    /// Creates a new Sentry event from an [`anyhow::Error`] like
+    /// [`Event::from_error`], with one exception for every error in its
+    /// [`chain`](anyhow::Error::chain). If the error captured a
+    /// [`backtrace`](anyhow::Error::backtrace), it's converted into the
+    /// stacktrace of the outermost exception, otherwise the stacktrace of the
+    /// current thread is used.
+    ///
+    /// # Examples
+    /// ```
+    /// # use anyhow::Context;
+    /// # use sentry_contrib_native::Event;
+    /// if let Err(error) = "test".parse::<i32>().context("failed to parse") {
+    ///     Event::from_anyhow(&error).capture();
+    /// }
+    /// ```
+    #[cfg(feature = "integration-anyhow")]
+    #[must_use = "`Event` doesn't do anything without `Event::capture`"]
+    pub fn from_anyhow(error: &anyhow::Error) -> Self {
+        Self::from_exceptions(
+            error::chain_exceptions(error.chain()),
+            backtrace::parse(&error.backtrace().to_string()),
+        )
+    }
+
+    /// Creates a new Sentry event from an [`eyre::Report`] like
+    /// [`Event::from_anyhow`]. The backtrace is only available with
+    /// [`eyre::DefaultHandler`], reports with other handlers get the stacktrace
+    /// of the current thread.
+    ///
+    /// # Examples
+    /// ```
+    /// # use eyre::WrapErr;
+    /// # use sentry_contrib_native::Event;
+    /// if let Err(report) = "test".parse::<i32>().wrap_err("failed to parse") {
+    ///     Event::from_eyre(&report).capture();
+    /// }
+    /// ```
+    #[cfg(feature = "integration-eyre")]
+    #[must_use = "`Event` doesn't do anything without `Event::capture`"]
+    pub fn from_eyre(report: &eyre::Report) -> Self {
+        Self::from_exceptions(
+            error::chain_exceptions(report.chain()),
+            Self::eyre_backtrace(report),
+        )
+    }
+
+    /// Extracts the backtrace captured by the [`eyre::DefaultHandler`] of
+    /// `report`, [`None`] for any other handler.
+    #[cfg(feature = "integration-eyre")]
+    fn eyre_backtrace(report: &eyre::Report) -> Option<Stacktrace> {
+        report.handler().downcast_ref::<eyre::DefaultHandler>()?;
+
+        // the default handler doesn't expose its backtrace, but always ends its
+        // output with the `Display` output of it
+        let debug = format!("{:?}", report);
+        let (_, backtrace) = debug.rsplit_once("\n\nStack backtrace:\n")?;
+        backtrace::parse(backtrace)
     }
 
-    /// Sends the [`Event`].
+    /// Creates a new Sentry event from `exceptions`, ordered from the
+    /// innermost to the outermost. The outermost is marked with a `generic`
+    /// mechanism and gets `stacktrace` or the stacktrace of the current thread.
+    fn from_exceptions(mut exceptions: Vec<Exception>, stacktrace: Option<Stacktrace>) -> Self {
+        if let Some(exception) = exceptions.last_mut() {
+            exception.mechanism = Some(Mechanism {
+                ty: Some("generic".into()),
+                handled: Some(true),
+                ..Mechanism::default()
+            });
+            exception.stacktrace =
+                stacktrace.or_else(|| Stacktrace::try_from(Self::exception_stacktrace(0)).ok());
+        }
+
+        let mut event = Self::new();
+        event.insert("exception", vec![("values", exceptions)]);
+        event
+    }
+
+    /// Sends the [`Event`]. Returns a nil [`Uuid`] if it was suppressed by a
+    /// [`Throttle`], see [`Options::set_throttle`].
     ///
     /// # Examples
     /// ```
@@ -209,10 +481,44 @@ impl Event {
     /// event.capture();
     /// ```
     #[allow(clippy::must_use_candidate)]
-    pub fn capture(self) -> Uuid {
+    pub fn capture(mut self) -> Uuid {
+        let suppressed = if let Some(suppressed) = throttle::acquire(self.throttle_kind()) {
+            suppressed
+        } else {
+            return Uuid::default();
+        };
+
+        if suppressed > 0 {
+            let extra = self
+                .map
+                .entry("extra".into())
+                .or_insert_with(|| Value::Map(BTreeMap::new()));
+
+            if let Some(extra) = extra.as_mut_map() {
+                extra.insert(
+                    "suppressed_events".into(),
+                    Value::Int(i32::try_from(suppressed).unwrap_or(i32::MAX)),
+                );
+            }
+        }
+
         let event = self.into_raw();
         Uuid(unsafe { sys::capture_event(event) })
     }
+
+    /// Yields the [`ThrottleKind`] this [`Event`] is limited by, see
+    /// [`Options::set_throttle`].
+    fn throttle_kind(&self) -> ThrottleKind {
+        match self.interface {
+            Interface::Message { .. } => ThrottleKind::Message,
+            Interface::Event
+                if self.map.contains_key("message") && !self.map.contains_key("exception") =>
+            {
+                ThrottleKind::Message
+            }
+            Interface::Event => ThrottleKind::Error,
+        }
+    }
 }
 
 /// A Sentry UUID.
@@ -277,6 +583,18 @@ impl Hash for Uuid {
 }
 
 impl Uuid {
+    /// Creates a new random UUID.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Uuid;
+    /// assert_ne!(Uuid::default(), Uuid::new_v4());
+    /// ```
+    #[must_use]
+    pub fn new_v4() -> Self {
+        Self(unsafe { sys::uuid_new_v4() })
+    }
+
     /// Creates a new empty UUID with the given `bytes`.
     ///
     /// # Examples
@@ -402,6 +720,81 @@ fn event() {
     let frames = stacktrace.get("frames").unwrap().as_list().unwrap();
     assert_ne!(None, frames.get(0).unwrap().as_map());
 
+    event.append_exception(Exception {
+        ty: Some("test type 2".into()),
+        thread_id: Some("2".into()),
+        ..Exception::default()
+    });
+
+    let values = event
+        .get("exception")
+        .and_then(|exception| exception.as_map()?.get("values")?.as_list())
+        .unwrap();
+    assert_eq!(2, values.len());
+    assert_eq!(
+        Some("test type"),
+        values[0].as_map().unwrap().get("type").unwrap().as_str()
+    );
+    let exception = Exception::try_from(values[1].clone()).unwrap();
+    assert_eq!(Some("test type 2"), exception.ty.as_deref());
+    assert_eq!(None, exception.value);
+    assert_eq!(Some("2"), exception.thread_id.as_deref());
+
+    event.capture();
+
+    let mut event = Event::new();
+    event.add_stacktrace(0);
+    event.append_thread(Thread {
+        id: Some("2".into()),
+        name: Some("worker".into()),
+        crashed: Some(true),
+        stacktrace: Some(Stacktrace::default()),
+        ..Thread::default()
+    });
+    event.append_thread(Thread {
+        current: Some(false),
+        ..Thread::default()
+    });
+
+    let values = event
+        .get("threads")
+        .and_then(|threads| threads.as_map()?.get("values")?.as_list())
+        .unwrap();
+    assert_eq!(3, values.len());
+    let thread = Thread::try_from(values[1].clone()).unwrap();
+    assert_eq!(Some("2"), thread.id.as_deref());
+    assert_eq!(Some("worker"), thread.name.as_deref());
+    assert_eq!(Some(true), thread.crashed);
+    assert!(thread.stacktrace.is_some());
+    let thread = Thread::try_from(values[2].clone()).unwrap();
+    assert_eq!(None, thread.id);
+    assert_eq!(None, thread.name);
+    assert_eq!(Some(false), thread.current);
+
+    event.capture();
+
+    // a plain list is appended to instead of replaced
+    let mut event = Event::new();
+    event.insert("threads", vec![Value::new(vec![("id", "1")])]);
+    event.append_thread(Thread {
+        id: Some("main".into()),
+        ..Thread::default()
+    });
+
+    let values = event
+        .get("threads")
+        .and_then(|threads| threads.as_map()?.get("values")?.as_list())
+        .unwrap();
+    assert_eq!(2, values.len());
+    assert_eq!(
+        Some("1"),
+        Thread::try_from(values[0].clone()).unwrap().id.as_deref()
+    );
+    assert_eq!(
+        Some("main"),
+        Thread::try_from(values[1].clone()).unwrap().id.as_deref()
+    );
+
     event.capture();
 }
 
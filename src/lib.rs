@@ -6,16 +6,29 @@
 )]
 #![doc = include_str!("../README.md")]
 
+#[cfg(any(
+    feature = "integration-anyhow",
+    feature = "integration-eyre",
+    feature = "symbolicate"
+))]
+mod backtrace;
 mod before_send;
 mod breadcrumb;
+mod error;
 mod event;
 mod ffi;
 mod logger;
+#[cfg(feature = "mock-server")]
+pub mod mock;
 mod object;
 mod options;
 mod panic;
+mod protocol;
 #[cfg(feature = "test")]
 pub mod test;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod throttle;
 mod transport;
 mod user;
 mod value;
@@ -23,6 +36,11 @@ mod value;
 pub use before_send::BeforeSend;
 use before_send::{Data as BeforeSendData, BEFORE_SEND};
 pub use breadcrumb::Breadcrumb;
+#[cfg(feature = "integration-anyhow")]
+pub use error::capture_anyhow;
+#[cfg(feature = "integration-eyre")]
+pub use error::capture_eyre;
+pub use error::{capture_error, ResultExt};
 pub use event::{Event, Interface, Uuid};
 use ffi::{CPath, CToR, RToC};
 #[cfg(feature = "transport-custom")]
@@ -34,21 +52,38 @@ use object::Object;
 use options::Ownership;
 pub use options::{Options, Shutdown};
 pub use panic::set_hook;
+pub use protocol::{
+    AppContext, BrowserContext, DebugImage, DebugMeta, DeviceContext, Exception, Frame, GpuContext,
+    LogEntry, Mechanism, OsContext, RequestInfo, RuntimeContext, Stacktrace, Thread,
+};
 use std::{
     convert::Infallible,
     fmt::{Display, Formatter, Result as FmtResult},
     os::raw::c_char,
     ptr,
+    time::Duration,
 };
 use thiserror::Error;
+pub use throttle::{Throttle, ThrottleKind};
+#[cfg(feature = "transport-rustls")]
+pub use transport::RustlsTransport;
 use transport::State as TransportState;
+pub use transport::{
+    statistics, DropReason, Envelope, RawEnvelope, Shutdown as TransportShutdown, Statistics,
+    Transport, API_VERSION, ENVELOPE_MIME, SDK_USER_AGENT,
+};
 #[cfg(feature = "transport-custom")]
-pub use transport::{Dsn, Error as TransportError, Parts, Request};
 pub use transport::{
-    Envelope, RawEnvelope, Shutdown as TransportShutdown, Transport, API_VERSION, ENVELOPE_MIME,
-    SDK_USER_AGENT,
+    Category, CheckInStatus, Compression, DiskQueue, Dsn, Encoding, EnvelopeBuilder, EnvelopeItem,
+    Error as TransportError, FileTransport, HttpConfig, HttpProxy, Layer, LayeredTransport,
+    MirrorTransport, ParsedEnvelope, Parts, RateLimiter, Request, RetryPolicy, RetryTransport,
+    RoutingTransport, Sink, SinkFormat, SinkTransport, TransportBuilder,
 };
+#[cfg(feature = "transport-tokio")]
+pub use transport::{Overflow, TokioTransport};
 pub use user::User;
+#[cfg(feature = "transport-custom")]
+use value::Json;
 pub use value::Value;
 
 /// Errors for this crate.
@@ -198,6 +233,38 @@ pub fn shutdown() {
 
     // de-allocate `LOGGER`
     LOGGER.lock().expect("failed to deallocate `LOGGER`").take();
+
+    throttle::clear();
+
+    #[cfg(feature = "symbolicate")]
+    backtrace::set_symbolicate(false);
+}
+
+/// Waits up to `timeout` until all envelopes captured so far were sent by the
+/// custom [`Transport`], without shutting down. Returns `true` if they were
+/// sent in time, see [`Transport::flush`].
+///
+/// sentry-native's built-in transports can't be flushed, in that case, or if
+/// Sentry wasn't initialized, this returns `false` right away.
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::{flush, Event, Options};
+/// # use std::time::Duration;
+/// # fn main() -> anyhow::Result<()> {
+/// let options = Options::new();
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+///
+/// if !flush(Duration::from_secs(2)) {
+///     eprintln!("failed to send all events in time");
+/// }
+/// # Ok(()) }
+/// ```
+#[must_use]
+pub fn flush(timeout: Duration) -> bool {
+    transport::flush(timeout)
 }
 
 /// This will lazily load and cache a list of all the loaded libraries.
@@ -531,7 +598,8 @@ pub fn set_level(level: Level) {
 }
 
 /// Starts a new session. By default sessions are started automatically on
-/// [`Options::init`].
+/// [`Options::init`]. Doesn't do anything if suppressed by a [`Throttle`], see
+/// [`Options::set_throttle`].
 ///
 /// # Examples
 /// ```
@@ -545,7 +613,9 @@ pub fn set_level(level: Level) {
 /// # Ok(()) }
 /// ```
 pub fn start_session() {
-    unsafe { sys::start_session() }
+    if throttle::acquire(ThrottleKind::Session).is_some() {
+        unsafe { sys::start_session() }
+    }
 }
 
 /// Prematurely end a session before it is done automatically by [`shutdown`].
@@ -725,3 +795,54 @@ fn threaded_stress() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn flush_custom() -> anyhow::Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SENT: AtomicUsize = AtomicUsize::new(0);
+    static FLUSHED: AtomicUsize = AtomicUsize::new(0);
+
+    struct CustomTransport;
+
+    impl Transport for CustomTransport {
+        fn send(&self, _: RawEnvelope) {
+            SENT.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn shutdown(self: Box<Self>, _: Duration) -> TransportShutdown {
+            TransportShutdown::Success
+        }
+
+        fn flush(&self, _: Duration) -> bool {
+            FLUSHED.store(SENT.load(Ordering::SeqCst), Ordering::SeqCst);
+            true
+        }
+    }
+
+    test::set_hook();
+
+    assert!(!crate::flush(Duration::from_millis(10)));
+
+    let mut options = Options::new();
+    options.set_transport(|_| Ok(CustomTransport));
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    assert!(crate::flush(Duration::from_secs(1)));
+    assert_eq!(SENT.load(Ordering::SeqCst), FLUSHED.load(Ordering::SeqCst));
+
+    // still initialized
+    Event::new().capture();
+    assert!(crate::flush(Duration::from_secs(1)));
+    assert_eq!(SENT.load(Ordering::SeqCst), FLUSHED.load(Ordering::SeqCst));
+    assert_ne!(0, FLUSHED.load(Ordering::SeqCst));
+
+    shutdown.shutdown();
+    assert!(!crate::flush(Duration::from_millis(10)));
+
+    test::verify_panics();
+
+    Ok(())
+}
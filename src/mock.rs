@@ -0,0 +1,942 @@
+//! Local mock of the Sentry ingestion endpoints for end-to-end tests.
+
+use crate::{Envelope, EnvelopeItem, ParsedEnvelope, Value};
+use ::{
+    flate2::read::GzDecoder,
+    std::{
+        collections::BTreeMap,
+        io::{self, BufRead, BufReader, Read, Write},
+        net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Condvar, Mutex, MutexGuard,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    thiserror::Error,
+};
+
+/// Maximum time a connection may stall before it's dropped.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum size of a request body, before and after decompression. Larger
+/// requests are answered with `413 Payload Too Large`.
+const MAX_BODY_SIZE: usize = 100 * 1024 * 1024;
+
+/// Error of [`read_request`] if the body is larger than [`MAX_BODY_SIZE`].
+#[derive(Debug, Error)]
+#[error("request body is too large")]
+struct TooLarge;
+
+/// Mock Sentry server listening on localhost, collecting everything sent to
+/// it instead of processing it. Point
+/// [`Options::set_dsn`](crate::Options::set_dsn) to [`Server::dsn`] to test the
+/// default transport, custom transports or crash uploads end-to-end without
+/// network access.
+///
+/// Accepts `POST` requests to `/api/<project>/envelope/` and
+/// `/api/<project>/minidump/`, which have to carry a valid `X-Sentry-Auth`
+/// header, or for minidumps alternatively a `sentry_key` query parameter, like
+/// Crashpad sends. Envelopes can be compressed with gzip, or Zstandard if the
+/// `compression-zstd` feature is enabled. Requests with a wrong key are
+/// answered with `401 Unauthorized` and counted, see [`Server::rejected`].
+/// Bodies larger than 100 MiB are answered with `413 Payload Too Large`.
+///
+/// The server is stopped when dropped.
+///
+/// Note that with the `test` feature enabled, the DSN is always taken from the
+/// `SENTRY_DSN` environment variable, which has to be set to [`Server::dsn`]
+/// instead.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{mock::Server, Event, Options};
+/// # use std::time::Duration;
+/// let server = Server::start()?;
+/// # // the `test` feature always uses `SENTRY_DSN`
+/// # std::env::set_var("SENTRY_DSN", server.dsn());
+///
+/// let mut options = Options::new();
+/// options.set_dsn(server.dsn());
+/// let shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// shutdown.shutdown();
+///
+/// # #[cfg(feature = "transport-default")]
+/// assert_eq!(1, server.wait_for_events(1, Duration::from_secs(10)).len());
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    /// Address the server is listening on.
+    address: SocketAddr,
+    /// Public key requests have to authenticate with.
+    key: String,
+    /// Project ID used in the DSN.
+    project: u64,
+    /// Shared with the server thread.
+    state: Arc<State>,
+    /// Thread accepting connections.
+    thread: Option<JoinHandle<()>>,
+}
+
+/// State shared between [`Server`] and its threads.
+#[derive(Debug, Default)]
+struct State {
+    /// Everything received so far.
+    received: Mutex<Received>,
+    /// Notifies about newly received requests.
+    condvar: Condvar,
+    /// Signals the server thread to stop.
+    stop: AtomicBool,
+}
+
+/// Requests received by [`Server`].
+#[derive(Debug, Default)]
+struct Received {
+    /// Envelopes, in the order they were received.
+    envelopes: Vec<ParsedEnvelope>,
+    /// Minidump uploads, in the order they were received.
+    minidumps: Vec<Minidump>,
+    /// Number of requests that failed authentication.
+    rejected: usize,
+}
+
+/// Minidump uploaded to a [`Server`] as `multipart/form-data`, like Crashpad
+/// does.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Minidump {
+    /// Form fields that aren't files, e.g. annotations.
+    pub fields: BTreeMap<String, String>,
+    /// Content of the `upload_file_minidump` file.
+    pub minidump: Vec<u8>,
+    /// All other files, by their field name, e.g. attachments or
+    /// `__sentry-event`.
+    pub attachments: BTreeMap<String, Vec<u8>>,
+}
+
+impl Minidump {
+    /// Decodes the event uploaded alongside the minidump, which is stored in
+    /// the `__sentry-event` attachment as `MessagePack`.
+    #[must_use]
+    pub fn event(&self) -> Option<Value> {
+        let event = self.attachments.get("__sentry-event")?;
+        Value::from_msgpack(event)
+    }
+}
+
+impl Server {
+    /// Public key of [`Server::start`].
+    pub const KEY: &'static str = "public";
+    /// Project ID of [`Server::start`].
+    pub const PROJECT: u64 = 1;
+
+    /// Starts a new [`Server`] on a random port, accepting
+    /// [`Server::KEY`] for [`Server::PROJECT`].
+    ///
+    /// # Errors
+    /// Fails if binding to localhost failed.
+    pub fn start() -> io::Result<Self> {
+        Self::with_key(Self::KEY, Self::PROJECT)
+    }
+
+    /// Starts a new [`Server`] on a random port, accepting `key` for
+    /// `project`.
+    ///
+    /// # Errors
+    /// Fails if binding to localhost failed.
+    pub fn with_key<S: Into<String>>(key: S, project: u64) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let key = key.into();
+        let state = Arc::new(State::default());
+
+        let thread = thread::spawn({
+            let key = key.clone();
+            let state = Arc::clone(&state);
+            move || {
+                for stream in listener.incoming() {
+                    if state.stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    if let Ok(stream) = stream {
+                        let endpoint = Endpoint {
+                            key: key.clone(),
+                            project,
+                            state: Arc::clone(&state),
+                        };
+                        thread::spawn(move || endpoint.handle(stream));
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            address,
+            key,
+            project,
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server is listening on.
+    #[must_use]
+    pub const fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// DSN pointing to this server, e.g. `http://public@127.0.0.1:12345/1`.
+    #[must_use]
+    pub fn dsn(&self) -> String {
+        format!("http://{}@{}/{}", self.key, self.address, self.project)
+    }
+
+    /// Returns all envelopes received so far.
+    #[must_use]
+    pub fn envelopes(&self) -> Vec<ParsedEnvelope> {
+        self.state.lock().envelopes.clone()
+    }
+
+    /// Returns the events of all envelopes received so far.
+    #[must_use]
+    pub fn events(&self) -> Vec<Value> {
+        events(&self.state.lock().envelopes)
+    }
+
+    /// Returns all minidumps received so far.
+    #[must_use]
+    pub fn minidumps(&self) -> Vec<Minidump> {
+        self.state.lock().minidumps.clone()
+    }
+
+    /// Number of requests that were rejected because of a missing or wrong
+    /// `X-Sentry-Auth` header.
+    #[must_use]
+    pub fn rejected(&self) -> usize {
+        self.state.lock().rejected
+    }
+
+    /// Forgets everything received so far.
+    pub fn clear(&self) {
+        let mut received = self.state.lock();
+        received.envelopes.clear();
+        received.minidumps.clear();
+        received.rejected = 0;
+    }
+
+    /// Waits until at least `count` events were received or `timeout` elapsed
+    /// and returns all events received so far, which are fewer than `count` if
+    /// it timed out.
+    #[must_use]
+    pub fn wait_for_events(&self, count: usize, timeout: Duration) -> Vec<Value> {
+        events(
+            &self
+                .state
+                .wait(timeout, |received| {
+                    events(&received.envelopes).len() >= count
+                })
+                .envelopes,
+        )
+    }
+
+    /// Waits until at least `count` minidumps were received or `timeout`
+    /// elapsed and returns all minidumps received so far, which are fewer than
+    /// `count` if it timed out.
+    #[must_use]
+    pub fn wait_for_minidumps(&self, count: usize, timeout: Duration) -> Vec<Minidump> {
+        self.state
+            .wait(timeout, |received| received.minidumps.len() >= count)
+            .minidumps
+            .clone()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::SeqCst);
+        // wake up the server thread
+        let _result = TcpStream::connect(self.address);
+
+        if let Some(thread) = self.thread.take() {
+            let _result = thread.join();
+        }
+    }
+}
+
+impl State {
+    /// Locks [`State::received`].
+    fn lock(&self) -> MutexGuard<'_, Received> {
+        self.received.lock().expect("lock poisoned")
+    }
+
+    /// Waits until `done` returns `true` or `timeout` elapsed.
+    fn wait<F: FnMut(&Received) -> bool>(
+        &self,
+        timeout: Duration,
+        mut done: F,
+    ) -> MutexGuard<'_, Received> {
+        let (received, _) = self
+            .condvar
+            .wait_timeout_while(self.lock(), timeout, |received| !done(received))
+            .expect("lock poisoned");
+
+        received
+    }
+}
+
+/// Extracts the payloads of all `event` items in `envelopes`.
+fn events(envelopes: &[ParsedEnvelope]) -> Vec<Value> {
+    envelopes
+        .iter()
+        .flat_map(|envelope| &envelope.items)
+        .filter(|item| item.item_type == "event")
+        .filter_map(EnvelopeItem::payload_value)
+        .collect()
+}
+
+/// Handles a single connection of [`Server`].
+struct Endpoint {
+    /// Public key requests have to authenticate with.
+    key: String,
+    /// Project ID requests have to be sent to.
+    project: u64,
+    /// Shared with [`Server`].
+    state: Arc<State>,
+}
+
+/// HTTP request received by [`Server`].
+#[derive(Debug)]
+struct Request {
+    /// Path without the query.
+    path: String,
+    /// Query parameters.
+    query: BTreeMap<String, String>,
+    /// Headers, with lowercase names.
+    headers: BTreeMap<String, String>,
+    /// Decoded body.
+    body: Vec<u8>,
+}
+
+impl Endpoint {
+    /// Reads a single request from `stream` and answers it.
+    fn handle(self, stream: TcpStream) {
+        let _result = stream.set_read_timeout(Some(TIMEOUT));
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        let mut reader = BufReader::new(stream);
+
+        let (status, body) = match read_request(&mut reader, &mut writer) {
+            Ok(Some(request)) => self.respond(&request),
+            // connection closed without a request, e.g. when stopping the server
+            Ok(None) => return,
+            Err(error) => {
+                let too_large = error
+                    .get_ref()
+                    .map_or(false, |error| error.is::<TooLarge>());
+                (if too_large { 413 } else { 400 }, String::new())
+            }
+        };
+
+        let _result = write!(
+            writer,
+            "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            reason(status),
+            body.len(),
+            body
+        );
+        let _result = writer.flush();
+        let _result = writer.shutdown(Shutdown::Both);
+    }
+
+    /// Processes `request`, returns the status code and body of the response.
+    fn respond(&self, request: &Request) -> (u16, String) {
+        let mut segments = request.path.trim_matches('/').split('/');
+
+        let (project, endpoint) = match (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) {
+            (Some("api"), Some(project), Some(endpoint), None) => (project, endpoint),
+            _ => return (404, String::new()),
+        };
+
+        if project != self.project.to_string() {
+            return (404, String::new());
+        }
+
+        let minidump = match endpoint {
+            "envelope" => false,
+            "minidump" => true,
+            _ => return (404, String::new()),
+        };
+
+        let key = request
+            .headers
+            .get("x-sentry-auth")
+            .and_then(|auth| auth_key(auth))
+            .or_else(|| {
+                if minidump {
+                    request.query.get("sentry_key").map(String::as_str)
+                } else {
+                    None
+                }
+            });
+
+        if key != Some(self.key.as_str()) {
+            self.state.lock().rejected += 1;
+            self.state.condvar.notify_all();
+            return (401, String::from("{\"detail\":\"invalid authentication\"}"));
+        }
+
+        if minidump {
+            let minidump = match request
+                .headers
+                .get("content-type")
+                .and_then(|content_type| multipart(content_type, &request.body))
+            {
+                Some(minidump) => minidump,
+                None => return (400, String::new()),
+            };
+
+            self.state.lock().minidumps.push(minidump);
+            self.state.condvar.notify_all();
+
+            (200, String::from("{}"))
+        } else {
+            let envelope = match Envelope::from(request.body.clone()).parse() {
+                Ok(envelope) => envelope,
+                Err(_) => return (400, String::new()),
+            };
+            let id = envelope
+                .header
+                .get("event_id")
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned);
+
+            self.state.lock().envelopes.push(envelope);
+            self.state.condvar.notify_all();
+
+            id.map_or_else(
+                || (200, String::from("{}")),
+                |id| (200, format!("{{\"id\":\"{}\"}}", id)),
+            )
+        }
+    }
+}
+
+/// Reads a request from `reader`, answering `Expect: 100-continue` on
+/// `writer`. Returns [`None`] if the connection was closed before a request
+/// was sent.
+fn read_request<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let target = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["POST", target, _] => (*target).to_owned(),
+        _ => return Err(invalid("unsupported request line")),
+    };
+
+    let (path, query) = target.split_at(target.find('?').unwrap_or(target.len()));
+    let query = query
+        .trim_start_matches('?')
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            Some((
+                pair.next()?.to_owned(),
+                pair.next().unwrap_or("").to_owned(),
+            ))
+        })
+        .collect();
+
+    let mut headers = BTreeMap::new();
+
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        let (name, value) =
+            header.split_at(header.find(':').ok_or_else(|| invalid("invalid header"))?);
+        headers.insert(
+            name.trim().to_ascii_lowercase(),
+            value[1..].trim().to_owned(),
+        );
+    }
+
+    let header_is = |name, expected: &str| {
+        headers
+            .get(name)
+            .map_or(false, |value: &String| value.eq_ignore_ascii_case(expected))
+    };
+
+    if header_is("expect", "100-continue") {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        writer.flush()?;
+    }
+
+    let body = if header_is("transfer-encoding", "chunked") {
+        read_chunked(reader)?
+    } else {
+        let length = headers
+            .get("content-length")
+            .map_or(Ok(0), |length| length.parse())
+            .map_err(|_| invalid("invalid content length"))?;
+
+        if length > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        body
+    };
+    let body = decode(headers.get("content-encoding").map(String::as_str), body)?;
+
+    Ok(Some(Request {
+        path: path.to_owned(),
+        query,
+        headers,
+        body,
+    }))
+}
+
+/// Reads a body with `Transfer-Encoding: chunked`.
+fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        // ignore chunk extensions
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))?;
+
+        if size == 0 {
+            // skip trailers
+            loop {
+                line.clear();
+
+                if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+
+        let start = body.len();
+
+        if size > MAX_BODY_SIZE - start {
+            return Err(too_large());
+        }
+
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+/// Decompresses `body` according to the `Content-Encoding` header.
+fn decode(encoding: Option<&str>, body: Vec<u8>) -> io::Result<Vec<u8>> {
+    let decoder: Box<dyn Read + '_> = match encoding {
+        None | Some("identity") => return Ok(body),
+        Some("gzip") => Box::new(GzDecoder::new(body.as_slice())),
+        #[cfg(feature = "compression-zstd")]
+        Some("zstd") => Box::new(zstd::stream::read::Decoder::new(body.as_slice())?),
+        Some(_) => return Err(invalid("unsupported content encoding")),
+    };
+
+    let mut decoded = Vec::new();
+    // read one byte more to detect bodies that are too large
+    decoder
+        .take(MAX_BODY_SIZE as u64 + 1)
+        .read_to_end(&mut decoded)?;
+
+    if decoded.len() > MAX_BODY_SIZE {
+        Err(too_large())
+    } else {
+        Ok(decoded)
+    }
+}
+
+/// Extracts `sentry_key` from an `X-Sentry-Auth` header.
+fn auth_key(auth: &str) -> Option<&str> {
+    auth.trim()
+        .strip_prefix("Sentry ")?
+        .split(',')
+        .filter_map(|pair| {
+            let mut pair = pair.trim().splitn(2, '=');
+            Some((pair.next()?, pair.next()?))
+        })
+        .find(|(key, _)| *key == "sentry_key")
+        .map(|(_, value)| value)
+}
+
+/// Decodes a `multipart/form-data` minidump upload.
+fn multipart(content_type: &str, body: &[u8]) -> Option<Minidump> {
+    let boundary = content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|parameter| parameter.strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("--{}", boundary);
+
+    let mut minidump = Minidump::default();
+    let mut found = false;
+
+    for part in split_bytes(body, delimiter.as_bytes()).into_iter().skip(1) {
+        // the closing delimiter is followed by `--`
+        if part.starts_with(b"--") {
+            break;
+        }
+
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        let (headers, content) = match find(part, b"\r\n\r\n") {
+            Some(position) => (
+                String::from_utf8_lossy(&part[..position]),
+                &part[position + 4..],
+            ),
+            None => continue,
+        };
+
+        let disposition = headers.lines().find_map(|header| {
+            let (name, value) = header.split_at(header.find(':')?);
+            if name.eq_ignore_ascii_case("content-disposition") {
+                Some(value[1..].to_owned())
+            } else {
+                None
+            }
+        })?;
+
+        let mut name = None;
+        let mut filename = None;
+
+        for parameter in disposition.split(';').map(str::trim) {
+            if let Some(value) = parameter.strip_prefix("name=") {
+                name = Some(value.trim_matches('"').to_owned());
+            } else if let Some(value) = parameter.strip_prefix("filename=") {
+                filename = Some(value.trim_matches('"').to_owned());
+            }
+        }
+
+        let name = name?;
+
+        if name == "upload_file_minidump" {
+            minidump.minidump = content.to_vec();
+            found = true;
+        } else if filename.is_some() {
+            minidump.attachments.insert(name, content.to_vec());
+        } else {
+            minidump
+                .fields
+                .insert(name, String::from_utf8_lossy(content).into_owned());
+        }
+    }
+
+    if found {
+        Some(minidump)
+    } else {
+        None
+    }
+}
+
+/// Splits `bytes` at every occurrence of `delimiter`.
+fn split_bytes<'a>(mut bytes: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+
+    while let Some(position) = find(bytes, delimiter) {
+        parts.push(&bytes[..position]);
+        bytes = &bytes[position + delimiter.len()..];
+    }
+
+    parts.push(bytes);
+    parts
+}
+
+/// Position of the first occurrence of `needle` in `bytes`.
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Reason phrase of the status codes used by [`Server`].
+const fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        413 => "Payload Too Large",
+        _ => "Not Found",
+    }
+}
+
+/// Creates an [`io::Error`] for a malformed request.
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Creates an [`io::Error`] for a request larger than [`MAX_BODY_SIZE`].
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, TooLarge)
+}
+
+/// Sends a raw HTTP request to `server` and returns the final status line.
+#[cfg(test)]
+fn send_raw(server: &Server, head: &str, body: &[u8]) -> io::Result<String> {
+    let mut stream = TcpStream::connect(server.address())?;
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    // skip `100 Continue`
+    Ok(response
+        .lines()
+        .rev()
+        .find(|line| line.starts_with("HTTP/1.1"))
+        .unwrap_or_default()
+        .to_owned())
+}
+
+#[test]
+fn server() -> anyhow::Result<()> {
+    use ::flate2::{write::GzEncoder, Compression};
+
+    let server = Server::start()?;
+    let auth = "X-Sentry-Auth: Sentry sentry_key=public, sentry_version=7\r\n";
+    let envelope = b"{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\"}\n\
+        {\"type\":\"event\",\"length\":13}\n\
+        {\"message\":1}\n";
+
+    assert_eq!(
+        format!("http://public@{}/1", server.address()),
+        server.dsn()
+    );
+
+    // plain
+    let head = format!(
+        "POST /api/1/envelope/ HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n",
+        auth,
+        envelope.len()
+    );
+    assert_eq!("HTTP/1.1 200 OK", send_raw(&server, &head, envelope)?);
+
+    // chunked, gzip and `Expect: 100-continue`
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(envelope)?;
+    let compressed = encoder.finish()?;
+    let mut body = format!("{:x}\r\n", compressed.len()).into_bytes();
+    body.extend(&compressed);
+    body.extend(b"\r\n0\r\n\r\n");
+    let head = format!(
+        "POST /api/1/envelope/ HTTP/1.1\r\n{}Transfer-Encoding: chunked\r\nContent-Encoding: gzip\r\nExpect: 100-continue\r\n\r\n",
+        auth
+    );
+    assert_eq!("HTTP/1.1 200 OK", send_raw(&server, &head, &body)?);
+
+    let events = server.wait_for_events(2, Duration::from_secs(10));
+    assert_eq!(2, events.len());
+    assert_eq!(Value::new(vec![("message", 1)]), events[0]);
+    assert_eq!(events, server.events());
+    assert_eq!(2, server.envelopes().len());
+
+    // wrong key, missing auth and unknown project
+    let head = format!(
+        "POST /api/1/envelope/ HTTP/1.1\r\nX-Sentry-Auth: Sentry sentry_key=wrong\r\nContent-Length: {}\r\n\r\n",
+        envelope.len()
+    );
+    assert_eq!(
+        "HTTP/1.1 401 Unauthorized",
+        send_raw(&server, &head, envelope)?
+    );
+    let head = format!(
+        "POST /api/1/envelope/ HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        envelope.len()
+    );
+    assert_eq!(
+        "HTTP/1.1 401 Unauthorized",
+        send_raw(&server, &head, envelope)?
+    );
+    assert_eq!(2, server.rejected());
+    let head = format!(
+        "POST /api/2/envelope/ HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n",
+        auth,
+        envelope.len()
+    );
+    assert_eq!(
+        "HTTP/1.1 404 Not Found",
+        send_raw(&server, &head, envelope)?
+    );
+    // malformed envelope
+    let head = format!(
+        "POST /api/1/envelope/ HTTP/1.1\r\n{}Content-Length: 3\r\n\r\n",
+        auth
+    );
+    assert_eq!(
+        "HTTP/1.1 400 Bad Request",
+        send_raw(&server, &head, b"{}{")?
+    );
+
+    // body too large, rejected before it's read
+    let head = format!(
+        "POST /api/1/envelope/ HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n",
+        auth,
+        MAX_BODY_SIZE + 1
+    );
+    assert_eq!(
+        "HTTP/1.1 413 Payload Too Large",
+        send_raw(&server, &head, b"")?
+    );
+
+    server.clear();
+    assert!(server.envelopes().is_empty());
+    assert!(server.minidumps().is_empty());
+    assert_eq!(0, server.rejected());
+
+    Ok(())
+}
+
+#[test]
+fn minidump() -> anyhow::Result<()> {
+    let server = Server::start()?;
+
+    // minidump authenticated by query
+    let mut body = b"--xyz\r\n\
+        Content-Disposition: form-data; name=\"sentry[release]\"\r\n\r\n\
+        1.0\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"upload_file_minidump\"; filename=\"a.dmp\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        MDMP\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"attachment.txt\"; filename=\"attachment.txt\"\r\n\r\n\
+        hello\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"__sentry-event\"; filename=\"__sentry-event\"\r\n\r\n"
+        .to_vec();
+    // `{"level": "fatal"}` as `MessagePack`
+    body.extend(b"\x81\xa5level\xa5fatal\r\n--xyz--\r\n");
+    let head = format!(
+        "POST /api/1/minidump/?sentry_key=public HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=xyz\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    assert_eq!("HTTP/1.1 200 OK", send_raw(&server, &head, &body)?);
+
+    // wrong key
+    let head = head.replace("sentry_key=public", "sentry_key=wrong");
+    assert_eq!(
+        "HTTP/1.1 401 Unauthorized",
+        send_raw(&server, &head, &body)?
+    );
+    assert_eq!(1, server.rejected());
+
+    let minidumps = server.wait_for_minidumps(1, Duration::from_secs(10));
+    assert_eq!(1, minidumps.len());
+    assert_eq!(
+        Some("1.0"),
+        minidumps[0]
+            .fields
+            .get("sentry[release]")
+            .map(String::as_str)
+    );
+    assert_eq!(b"MDMP", minidumps[0].minidump.as_slice());
+    assert_eq!(
+        Some(b"hello".as_ref()),
+        minidumps[0]
+            .attachments
+            .get("attachment.txt")
+            .map(Vec::as_slice)
+    );
+    assert_eq!(
+        Some(Value::new(vec![("level", "fatal")])),
+        minidumps[0].event()
+    );
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "transport-default"))]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn default_transport() -> anyhow::Result<()> {
+    use crate::{test, Event, Options};
+    use std::env;
+
+    test::set_hook();
+
+    let server = Server::start()?;
+    env::set_var("SENTRY_DSN", server.dsn());
+
+    let shutdown = Options::new().init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    assert_eq!(3, server.wait_for_events(3, Duration::from_secs(10)).len());
+    assert_eq!(0, server.rejected());
+
+    test::verify_panics();
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "transport-rustls"))]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn custom_transport() -> anyhow::Result<()> {
+    use crate::{set_tag, test, test_util, Event, Options, RustlsTransport};
+    use std::env;
+
+    test::set_hook();
+
+    let server = Server::start()?;
+    env::set_var("SENTRY_DSN", server.dsn());
+
+    let mut options = Options::new();
+    options.set_transport(RustlsTransport::new);
+    let shutdown = options.init()?;
+
+    set_tag("test", "mock");
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    let events = server.wait_for_events(3, Duration::from_secs(10));
+    assert_eq!(3, events.len());
+
+    for event in &events {
+        assert!(test_util::has_tag(event, "test", "mock"));
+    }
+
+    test::verify_panics();
+
+    Ok(())
+}
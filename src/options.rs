@@ -1,21 +1,36 @@
 //! Sentry options implementation.
 
+#[cfg(feature = "symbolicate")]
+use crate::backtrace;
 use crate::{
-    before_send, logger, transport, BeforeSend, BeforeSendData, CPath, CToR, Error, Logger,
-    LoggerData, RToC, Transport, TransportState, BEFORE_SEND, LOGGER,
+    before_send, logger, throttle, transport, BeforeSend, BeforeSendData, CPath, CToR, Error,
+    Logger, LoggerData, RToC, Statistics, Throttle, ThrottleKind, Transport, TransportState,
+    BEFORE_SEND, LOGGER,
 };
 #[cfg(doc)]
-use crate::{end_session, set_user_consent, shutdown, start_session, Consent, Event};
-#[cfg(feature = "test")]
+use crate::{
+    end_session, set_hook, set_user_consent, shutdown, start_session, Consent, DropReason, Event,
+};
+#[cfg(feature = "transport-custom")]
+use crate::{HttpConfig, TransportError};
+use once_cell::sync::Lazy;
+#[cfg(any(feature = "test", feature = "transport-custom"))]
 use std::env;
 #[cfg(doc)]
 use std::process::abort;
 use std::{
+    collections::BTreeMap,
     fmt::{Debug, Formatter, Result as FmtResult},
     mem,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
+/// Passes [`Options::database_path`] to the [`Options`] given to the
+/// [`Transport`] startup function, as it can't be retrieved from
+/// sentry-native.
+static DATABASE_PATH: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
 /// The Sentry client options.
 ///
 /// # Examples
@@ -37,6 +52,16 @@ pub struct Options {
     /// Storing [`Options::set_logger`] data to save it globally on
     /// [`Options::init`] and properly deallocate it on [`shutdown`].
     logger: Option<LoggerData>,
+    /// Storing [`Options::set_database_path`] to make it available with
+    /// [`Options::database_path`].
+    database_path: Option<PathBuf>,
+    /// Storing [`Options::set_throttle`] to save it globally on
+    /// [`Options::init`].
+    throttles: BTreeMap<ThrottleKind, Throttle>,
+    /// Storing [`Options::set_rust_symbolication`] to save it globally on
+    /// [`Options::init`].
+    #[cfg(feature = "symbolicate")]
+    rust_symbolication: bool,
 }
 
 /// Represents the ownership status of [`Options`].
@@ -80,7 +105,11 @@ impl Debug for Options {
                     &"None"
                 },
             )
-            .finish()
+            .field("database_path", &self.database_path)
+            .field("throttles", &self.throttles);
+        #[cfg(feature = "symbolicate")]
+        debug.field("rust_symbolication", &self.rust_symbolication);
+        debug.finish()
     }
 }
 
@@ -123,6 +152,19 @@ impl Options {
             dsn: None,
             before_send: None,
             logger: None,
+            database_path: match options {
+                Ownership::Owned(_) => None,
+                Ownership::Borrowed(_) => DATABASE_PATH.lock().expect("lock poisoned").clone(),
+            },
+            throttles: match options {
+                Ownership::Owned(_) => BTreeMap::new(),
+                Ownership::Borrowed(_) => throttle::get(),
+            },
+            #[cfg(feature = "symbolicate")]
+            rust_symbolication: match options {
+                Ownership::Owned(_) => false,
+                Ownership::Borrowed(_) => backtrace::symbolicate(),
+            },
         };
 
         #[cfg(feature = "test")]
@@ -450,6 +492,40 @@ impl Options {
         unsafe { sys::options_get_ca_certs(self.as_ref()).as_str() }
     }
 
+    /// Builds the [`HttpConfig`] for a custom [`Transport`] from
+    /// [`Options::http_proxy`], [`Options::ca_certs`] and the `NO_PROXY`
+    /// environment variable, which sentry-native's built-in transport honors
+    /// too. The shipped HTTP transports apply it automatically.
+    ///
+    /// # Errors
+    /// - Fails with [`TransportError::UrlParse`] if the proxy isn't a valid
+    ///   URL.
+    /// - Fails with [`TransportError::Proxy`] if the proxy has no host or
+    ///   doesn't have a http(s) scheme, SOCKS proxies aren't supported by the
+    ///   shipped transports.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Options;
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut options = Options::new();
+    /// options.set_http_proxy("http://some.proxy/");
+    /// options.set_ca_certs("certs.pem");
+    /// let config = options.http_config()?;
+    ///
+    /// assert_eq!(
+    ///     Some("some.proxy"),
+    ///     config.proxy().and_then(|proxy| proxy.url().host_str())
+    /// );
+    /// assert!(config.ca_certs().unwrap().ends_with("certs.pem"));
+    /// # Ok(()) }
+    /// ```
+    #[cfg(feature = "transport-custom")]
+    pub fn http_config(&self) -> Result<HttpConfig, TransportError> {
+        let no_proxy = env::var("NO_PROXY").or_else(|_| env::var("no_proxy")).ok();
+        HttpConfig::new(self.http_proxy(), no_proxy.as_deref(), self.ca_certs())
+    }
+
     /// Configures the name of the default transport thread. Has no effect when
     /// using a custom transport.
     ///
@@ -687,6 +763,44 @@ impl Options {
         }
     }
 
+    /// Enables or disables in-process symbolication of the stacktraces this
+    /// crate captures, e.g. with [`Event::add_stacktrace`],
+    /// [`Event::from_error`] or the panic handler of [`set_hook`]. Disabled
+    /// by default.
+    ///
+    /// Unlike [`Options::set_symbolize_stacktraces`], frames get demangled
+    /// function names, file, line and column from the debug information of the
+    /// running binary, which makes them readable without uploading debug
+    /// information files. Crashes captured by the backend aren't affected.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Options;
+    /// let mut options = Options::new();
+    /// options.set_rust_symbolication(true);
+    /// ```
+    #[cfg(feature = "symbolicate")]
+    pub fn set_rust_symbolication(&mut self, enabled: bool) {
+        self.rust_symbolication = enabled;
+    }
+
+    /// Returns `true` if in-process symbolication is enabled, see
+    /// [`Options::set_rust_symbolication`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Options;
+    /// let mut options = Options::new();
+    /// options.set_rust_symbolication(true);
+    ///
+    /// assert!(options.rust_symbolication());
+    /// ```
+    #[cfg(feature = "symbolicate")]
+    #[must_use]
+    pub const fn rust_symbolication(&self) -> bool {
+        self.rust_symbolication
+    }
+
     /// Adds a new attachment to be sent along.
     ///
     /// # Examples
@@ -768,11 +882,11 @@ impl Options {
     /// ```
     pub fn set_database_path<P: Into<PathBuf>>(&mut self, path: P) {
         #[cfg(feature = "test")]
-        let path = PathBuf::from(env!("OUT_DIR"))
-            .join(path.into())
-            .into_os_vec();
+        let path = PathBuf::from(env!("OUT_DIR")).join(path.into());
         #[cfg(not(feature = "test"))]
-        let path = path.into().into_os_vec();
+        let path = path.into();
+        self.database_path = Some(path.clone());
+        let path = path.into_os_vec();
 
         #[cfg(windows)]
         unsafe {
@@ -784,6 +898,26 @@ impl Options {
         };
     }
 
+    /// Yields the path set with [`Options::set_database_path`], sentry-native
+    /// defaults to `.sentry-native` in the current working directory if none
+    /// was set.
+    ///
+    /// This is also available in the [`Options`] passed to the startup
+    /// function of [`Options::set_transport`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Options;
+    /// let mut options = Options::new();
+    /// options.set_database_path(".sentry-native");
+    ///
+    /// assert!(options.database_path().is_some());
+    /// ```
+    #[must_use]
+    pub fn database_path(&self) -> Option<&Path> {
+        self.database_path.as_deref()
+    }
+
     /// Enables forwarding to the system crash reporter. Disabled by default.
     ///
     /// This setting only has an effect when using Crashpad on macOS. If
@@ -802,6 +936,49 @@ impl Options {
         unsafe { sys::options_set_system_crash_reporter_enabled(self.as_mut(), enabled) }
     }
 
+    /// Limits how many events of `kind` are sent with a [`Throttle`], before
+    /// they reach the [`Transport`]. Events and sessions suppressed by it are
+    /// counted as [`DropReason::Throttled`] in [`Statistics`], the number of
+    /// events suppressed since the last allowed one is added to the next
+    /// allowed event as the `suppressed_events` extra.
+    ///
+    /// Only events captured with [`Event::capture`] and sessions started with
+    /// [`start_session`] are throttled, crashes and sessions started
+    /// automatically by sentry-native aren't.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Options, Throttle, ThrottleKind};
+    /// let mut options = Options::new();
+    /// options.set_throttle(ThrottleKind::Error, Throttle::per_minute(10, 5));
+    /// options.set_throttle(ThrottleKind::Message, Throttle::per_minute(60, 10));
+    /// ```
+    pub fn set_throttle(&mut self, kind: ThrottleKind, throttle: Throttle) {
+        self.throttles.insert(kind, throttle);
+    }
+
+    /// Yields the [`Throttle`] set with [`Options::set_throttle`] for `kind`.
+    ///
+    /// This is also available in the [`Options`] passed to the startup
+    /// function of [`Options::set_transport`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Options, Throttle, ThrottleKind};
+    /// let mut options = Options::new();
+    /// options.set_throttle(ThrottleKind::Session, Throttle::per_minute(1, 1));
+    ///
+    /// assert_eq!(
+    ///     Some(Throttle::per_minute(1, 1)),
+    ///     options.throttle(ThrottleKind::Session)
+    /// );
+    /// assert_eq!(None, options.throttle(ThrottleKind::Error));
+    /// ```
+    #[must_use]
+    pub fn throttle(&self, kind: ThrottleKind) -> Option<Throttle> {
+        self.throttles.get(&kind).copied()
+    }
+
     /// Initializes the Sentry SDK with the specified options. Make sure to
     /// capture the resulting [`Shutdown`], this makes sure to automatically
     /// call [`shutdown`] when it drops.
@@ -841,12 +1018,29 @@ impl Options {
             lock
         });
 
-        match unsafe { sys::init(options) } {
+        // statistics are counted from every new start
+        transport::reset_statistics();
+
+        // has to be in place before `sys::init` to make it available to the
+        // transport
+        throttle::set(&self.throttles);
+        #[cfg(feature = "symbolicate")]
+        backtrace::set_symbolicate(self.rust_symbolication);
+
+        // only needed during `sys::init`, where the transport is started
+        *DATABASE_PATH.lock().expect("lock poisoned") = self.database_path.take();
+        let result = unsafe { sys::init(options) };
+        DATABASE_PATH.lock().expect("lock poisoned").take();
+
+        match result {
             0 => Ok(Shutdown),
             1 => {
                 // deallocate globals on failure, which are otherwise unused
                 before_send.take().take();
                 logger.take().take();
+                throttle::clear();
+                #[cfg(feature = "symbolicate")]
+                backtrace::set_symbolicate(false);
 
                 Err(Error::Init)
             }
@@ -935,6 +1129,28 @@ impl Shutdown {
     pub fn shutdown(self) {
         drop(self);
     }
+
+    /// Returns a handle to the delivery statistics, which stays usable after
+    /// shutting down, see [`Statistics`].
+    ///
+    /// # Examples
+    /// ```
+    /// # fn main() -> anyhow::Result<()> {
+    /// # use sentry_contrib_native::{Event, Options};
+    /// let options = Options::new();
+    /// let shutdown = options.init()?;
+    /// let statistics = shutdown.statistics();
+    ///
+    /// Event::new().capture();
+    /// shutdown.shutdown();
+    ///
+    /// println!("failed to send {} envelopes", statistics.failed());
+    /// # Ok(()) }
+    /// ```
+    #[must_use]
+    pub fn statistics(&self) -> Statistics {
+        transport::statistics()
+    }
 }
 
 #[test]
@@ -1027,14 +1243,29 @@ fn options() -> anyhow::Result<()> {
     options.set_symbolize_stacktraces(true);
     assert!(options.symbolize_stacktraces());
 
+    #[cfg(feature = "symbolicate")]
+    {
+        assert!(!options.rust_symbolication());
+        options.set_rust_symbolication(true);
+        assert!(options.rust_symbolication());
+    }
+
     options.add_attachment("server.log");
 
     options.set_handler_path("crashpad_handler");
 
     options.set_database_path(".sentry-native");
+    assert!(options.database_path().unwrap().ends_with(".sentry-native"));
 
     options.set_system_crash_reporter(true);
 
+    assert_eq!(None, options.throttle(ThrottleKind::Error));
+    options.set_throttle(ThrottleKind::Error, Throttle::per_minute(10, 5));
+    assert_eq!(
+        Some(Throttle::per_minute(10, 5)),
+        options.throttle(ThrottleKind::Error)
+    );
+
     Ok(())
 }
 
@@ -0,0 +1,668 @@
+//! Typed Sentry protocol interfaces, see
+//! <https://develop.sentry.dev/sdk/event-payloads/>.
+//!
+//! All types convert into [`Value`] and can be parsed back from it with
+//! [`TryFrom`], which makes them usable when building an [`Event`] as well as
+//! inside [`BeforeSend`]. Fields not covered by a type, or with a value that
+//! doesn't fit the type of the field, are kept in its `other` map, so parsing
+//! and converting back doesn't lose any data.
+
+#[cfg(doc)]
+use crate::{BeforeSend, Event};
+use crate::{Error, Map, Object, Value};
+use std::{collections::BTreeMap, convert::TryFrom};
+
+/// Conversion of protocol fields from and into [`Value`].
+trait Field: Sized {
+    /// Converts the field into a [`Value`].
+    fn into_value(self) -> Value;
+
+    /// Parses the field from a [`Value`], [`None`] if it has the wrong type.
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+impl Field for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl Field for bool {
+    fn into_value(self) -> Value {
+        self.into()
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+/// Whole doubles are accepted too, e.g. after a round-trip through JSON.
+impl Field for i32 {
+    fn into_value(self) -> Value {
+        self.into()
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::float_cmp)]
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Int(value) => Some(value),
+            Value::Double(value)
+                if value.trunc() == value
+                    && value >= Self::MIN.into()
+                    && value <= Self::MAX.into() =>
+            {
+                Some(value as Self)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Field for f64 {
+    fn into_value(self) -> Value {
+        self.into()
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Int(value) => Some(value.into()),
+            Value::Double(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Numbers are accepted too, e.g. thread IDs can be either.
+impl Field for String {
+    fn into_value(self) -> Value {
+        self.into()
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Int(value) => Some(value.to_string()),
+            Value::Double(value) => Some(value.to_string()),
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<F: Field> Field for Vec<F> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(Field::into_value).collect())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        value
+            .into_list()
+            .ok()?
+            .into_iter()
+            .map(Field::from_value)
+            .collect()
+    }
+}
+
+impl<F: Field> Field for BTreeMap<String, F> {
+    fn into_value(self) -> Value {
+        Value::Map(
+            self.into_iter()
+                .map(|(key, value)| (key, value.into_value()))
+                .collect(),
+        )
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        value
+            .into_map()
+            .ok()?
+            .into_iter()
+            .map(|(key, value)| Some((key, F::from_value(value)?)))
+            .collect()
+    }
+}
+
+/// Removes `key` from `map` and parses it, a missing key or `null` yield
+/// [`None`]. A value with the wrong type is left in `map` and yields [`None`].
+fn take<F: Field>(map: &mut BTreeMap<String, Value>, key: &str) -> Option<F> {
+    match map.remove(key) {
+        None | Some(Value::Null) => None,
+        Some(value) => {
+            let field = F::from_value(value.clone());
+
+            if field.is_none() {
+                map.insert(key.into(), value);
+            }
+
+            field
+        }
+    }
+}
+
+/// Declares a protocol interface with the given optional fields and
+/// implements [`Field`], [`Map`], [`From`] and [`TryFrom`] for it. Contexts
+/// additionally take the value of their `type` field.
+macro_rules! protocol {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident $(: $context:literal)? {
+            $(
+                $(#[$field_meta:meta])*
+                $field:ident: $key:literal => $ty:ty,
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, Default, PartialEq)]
+        pub struct $name {
+            $(
+                $(#[$field_meta])*
+                pub $field: Option<$ty>,
+            )*
+            /// Additional fields.
+            pub other: BTreeMap<String, Value>,
+        }
+
+        impl Field for $name {
+            fn into_value(self) -> Value {
+                #[allow(unused_mut)]
+                let mut map = self.other;
+                $(
+                    if let Some(value) = self.$field {
+                        map.insert($key.into(), value.into_value());
+                    }
+                )*
+                $(map.insert("type".into(), $context.into());)?
+
+                Value::Map(map)
+            }
+
+            fn from_value(value: Value) -> Option<Self> {
+                #[allow(unused_mut)]
+                let mut map = value.into_map().ok()?;
+                $(
+                    if map.get("type").and_then(Value::as_str) == Some($context) {
+                        map.remove("type");
+                    }
+                )?
+
+                Some(Self {
+                    $($field: take(&mut map, $key),)*
+                    other: map,
+                })
+            }
+        }
+
+        impl Object for $name {
+            fn into_parts(self) -> (sys::Value, BTreeMap<String, Value>) {
+                let map = self
+                    .into_value()
+                    .into_map()
+                    .expect("protocol types are always `Value::Map`");
+
+                (unsafe { sys::value_new_object() }, map)
+            }
+        }
+
+        impl Map for $name {}
+
+        impl From<$name> for Value {
+            fn from(value: $name) -> Self {
+                value.into_value()
+            }
+        }
+
+        impl TryFrom<Value> for $name {
+            type Error = Error;
+
+            fn try_from(value: Value) -> Result<Self, Error> {
+                Field::from_value(value.clone()).ok_or(Error::TryConvert(value))
+            }
+        }
+    };
+}
+
+protocol! {
+    /// Exception interface, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/exception/>.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Event, Exception, Mechanism};
+    /// let mut event = Event::new();
+    /// event.add_exception(
+    ///     Exception {
+    ///         ty: Some("ParseIntError".into()),
+    ///         value: Some("invalid digit found in string".into()),
+    ///         mechanism: Some(Mechanism {
+    ///             ty: Some("generic".into()),
+    ///             handled: Some(true),
+    ///             ..Mechanism::default()
+    ///         }),
+    ///         ..Exception::default()
+    ///     },
+    ///     0,
+    /// );
+    /// event.capture();
+    /// ```
+    pub struct Exception {
+        /// Type of the exception, e.g. `ValueError`.
+        ty: "type" => String,
+        /// Value of the exception, e.g. the error message.
+        value: "value" => String,
+        /// Module or package the type is defined in.
+        module: "module" => String,
+        /// ID of the [`Thread`] the exception occurred in.
+        thread_id: "thread_id" => String,
+        /// How the exception was captured.
+        mechanism: "mechanism" => Mechanism,
+        /// Stacktrace of the exception.
+        stacktrace: "stacktrace" => Stacktrace,
+    }
+}
+
+protocol! {
+    /// Mechanism an [`Exception`] was captured by, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/exception/#exception-mechanism>.
+    pub struct Mechanism {
+        /// Type of the mechanism, e.g. `panic` or `generic`.
+        ty: "type" => String,
+        /// Human-readable description.
+        description: "description" => String,
+        /// Link to documentation about the mechanism.
+        help_link: "help_link" => String,
+        /// If the exception was handled by the application.
+        handled: "handled" => bool,
+        /// If the exception was created synthetically, e.g. from a signal.
+        synthetic: "synthetic" => bool,
+        /// Arbitrary data.
+        data: "data" => BTreeMap<String, Value>,
+        /// Operating system specific information, e.g. the signal.
+        meta: "meta" => BTreeMap<String, Value>,
+    }
+}
+
+protocol! {
+    /// Stacktrace interface, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/stacktrace/>.
+    pub struct Stacktrace {
+        /// Frames, ordered from the oldest call to the most recent one.
+        frames: "frames" => Vec<Frame>,
+        /// Register values of the most recent frame.
+        registers: "registers" => BTreeMap<String, String>,
+    }
+}
+
+protocol! {
+    /// Frame of a [`Stacktrace`], see
+    /// <https://develop.sentry.dev/sdk/event-payloads/stacktrace/#frame-attributes>.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Frame, Value};
+    /// # use std::convert::TryFrom;
+    /// # fn main() -> anyhow::Result<()> {
+    /// let frame = Frame::try_from(Value::new(vec![
+    ///     ("function", Value::new("main")),
+    ///     ("lineno", Value::new(10)),
+    /// ]))?;
+    ///
+    /// assert_eq!(Some("main"), frame.function.as_deref());
+    /// assert_eq!(Some(10), frame.lineno);
+    /// # Ok(()) }
+    /// ```
+    pub struct Frame {
+        /// Name of the function.
+        function: "function" => String,
+        /// Original, possibly mangled, name of the function.
+        raw_function: "raw_function" => String,
+        /// Mangled name of the function.
+        symbol: "symbol" => String,
+        /// Module the frame belongs to.
+        module: "module" => String,
+        /// Path to the binary the frame belongs to.
+        package: "package" => String,
+        /// Relative path to the source file.
+        filename: "filename" => String,
+        /// Absolute path to the source file.
+        abs_path: "abs_path" => String,
+        /// Line number.
+        lineno: "lineno" => i32,
+        /// Column number.
+        colno: "colno" => i32,
+        /// Source lines before `context_line`.
+        pre_context: "pre_context" => Vec<String>,
+        /// Source line of `lineno`.
+        context_line: "context_line" => String,
+        /// Source lines after `context_line`.
+        post_context: "post_context" => Vec<String>,
+        /// If the frame belongs to the application and not a library.
+        in_app: "in_app" => bool,
+        /// Local variables.
+        vars: "vars" => BTreeMap<String, Value>,
+        /// Hex encoded instruction address, e.g. `0x7f1234`.
+        instruction_addr: "instruction_addr" => String,
+        /// Hex encoded address of the function.
+        symbol_addr: "symbol_addr" => String,
+        /// Hex encoded address of the image the frame belongs to.
+        image_addr: "image_addr" => String,
+        /// Platform of the frame, if different from the event.
+        platform: "platform" => String,
+    }
+}
+
+protocol! {
+    /// Thread interface, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/threads/>.
+    pub struct Thread {
+        /// ID of the thread.
+        id: "id" => String,
+        /// Name of the thread.
+        name: "name" => String,
+        /// If the thread crashed.
+        crashed: "crashed" => bool,
+        /// If the thread was in the foreground.
+        current: "current" => bool,
+        /// Stacktrace of the thread.
+        stacktrace: "stacktrace" => Stacktrace,
+    }
+}
+
+protocol! {
+    /// Debug meta interface, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/debugmeta/>.
+    pub struct DebugMeta {
+        /// Information about the system SDK, e.g. on Apple platforms.
+        sdk_info: "sdk_info" => BTreeMap<String, Value>,
+        /// Loaded images.
+        images: "images" => Vec<DebugImage>,
+    }
+}
+
+protocol! {
+    /// Image of [`DebugMeta`], see
+    /// <https://develop.sentry.dev/sdk/event-payloads/debugmeta/#native-debug-images>.
+    pub struct DebugImage {
+        /// Type of the image, e.g. `elf`, `macho` or `pe`.
+        ty: "type" => String,
+        /// Hex encoded load address.
+        image_addr: "image_addr" => String,
+        /// Size of the image in bytes.
+        image_size: "image_size" => i32,
+        /// Hex encoded preferred load address.
+        image_vmaddr: "image_vmaddr" => String,
+        /// Path to the image.
+        code_file: "code_file" => String,
+        /// Platform specific identifier of the image.
+        code_id: "code_id" => String,
+        /// Path to the debug file.
+        debug_file: "debug_file" => String,
+        /// Identifier of the debug file.
+        debug_id: "debug_id" => String,
+        /// CPU architecture.
+        arch: "arch" => String,
+    }
+}
+
+protocol! {
+    /// Message interface, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/message/>.
+    pub struct LogEntry {
+        /// Raw message, possibly with placeholders.
+        message: "message" => String,
+        /// Formatted message.
+        formatted: "formatted" => String,
+        /// Parameters of the placeholders.
+        params: "params" => Vec<Value>,
+    }
+}
+
+protocol! {
+    /// Request interface, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/request/>.
+    pub struct RequestInfo {
+        /// URL of the request, without the query string.
+        url: "url" => String,
+        /// HTTP method.
+        method: "method" => String,
+        /// Request body.
+        data: "data" => Value,
+        /// Query string.
+        query_string: "query_string" => String,
+        /// Cookie header.
+        cookies: "cookies" => String,
+        /// Headers.
+        headers: "headers" => BTreeMap<String, String>,
+        /// Environment of the server, e.g. CGI variables.
+        env: "env" => BTreeMap<String, String>,
+    }
+}
+
+protocol! {
+    /// Operating system context, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/contexts/#os-context>.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{Event, OsContext};
+    /// let mut event = Event::new();
+    /// event.insert(
+    ///     "contexts",
+    ///     vec![(
+    ///         "os",
+    ///         OsContext {
+    ///             name: Some("Linux".into()),
+    ///             kernel_version: Some("5.10.0".into()),
+    ///             ..OsContext::default()
+    ///         },
+    ///     )],
+    /// );
+    /// event.capture();
+    /// ```
+    pub struct OsContext: "os" {
+        /// Name of the operating system.
+        name: "name" => String,
+        /// Version of the operating system.
+        version: "version" => String,
+        /// Build number.
+        build: "build" => String,
+        /// Kernel version.
+        kernel_version: "kernel_version" => String,
+        /// If the operating system was jailbroken or rooted.
+        rooted: "rooted" => bool,
+    }
+}
+
+protocol! {
+    /// Device context, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/contexts/#device-context>.
+    pub struct DeviceContext: "device" {
+        /// Name of the device.
+        name: "name" => String,
+        /// Family of the device, e.g. `iPhone`.
+        family: "family" => String,
+        /// Model of the device.
+        model: "model" => String,
+        /// Internal model identifier.
+        model_id: "model_id" => String,
+        /// CPU architecture.
+        arch: "arch" => String,
+        /// Manufacturer.
+        manufacturer: "manufacturer" => String,
+        /// Brand.
+        brand: "brand" => String,
+        /// Battery level in percent.
+        battery_level: "battery_level" => f64,
+        /// Orientation, `portrait` or `landscape`.
+        orientation: "orientation" => String,
+        /// If the device is a simulator.
+        simulator: "simulator" => bool,
+        /// Total memory in bytes.
+        memory_size: "memory_size" => f64,
+        /// Free memory in bytes.
+        free_memory: "free_memory" => f64,
+        /// Boot time in ISO 8601 format.
+        boot_time: "boot_time" => String,
+        /// Timezone.
+        timezone: "timezone" => String,
+    }
+}
+
+protocol! {
+    /// Runtime context, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/contexts/#runtime-context>.
+    pub struct RuntimeContext: "runtime" {
+        /// Name of the runtime, e.g. `rustc`.
+        name: "name" => String,
+        /// Version of the runtime.
+        version: "version" => String,
+        /// Unprocessed description of the runtime.
+        raw_description: "raw_description" => String,
+    }
+}
+
+protocol! {
+    /// App context, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/contexts/#app-context>.
+    pub struct AppContext: "app" {
+        /// Start time of the application in ISO 8601 format.
+        app_start_time: "app_start_time" => String,
+        /// Hash identifying the device and application.
+        device_app_hash: "device_app_hash" => String,
+        /// Build type, e.g. `debug`.
+        build_type: "build_type" => String,
+        /// Identifier of the application, e.g. the bundle ID.
+        app_identifier: "app_identifier" => String,
+        /// Name of the application.
+        app_name: "app_name" => String,
+        /// Version of the application.
+        app_version: "app_version" => String,
+        /// Build number of the application.
+        app_build: "app_build" => String,
+    }
+}
+
+protocol! {
+    /// Browser context, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/contexts/#browser-context>.
+    pub struct BrowserContext: "browser" {
+        /// Name of the browser.
+        name: "name" => String,
+        /// Version of the browser.
+        version: "version" => String,
+    }
+}
+
+protocol! {
+    /// GPU context, see
+    /// <https://develop.sentry.dev/sdk/event-payloads/contexts/#gpu-context>.
+    pub struct GpuContext: "gpu" {
+        /// Name of the GPU.
+        name: "name" => String,
+        /// Version of the GPU.
+        version: "version" => String,
+        /// PCI identifier of the GPU.
+        id: "id" => String,
+        /// PCI vendor identifier.
+        vendor_id: "vendor_id" => String,
+        /// Vendor name.
+        vendor_name: "vendor_name" => String,
+        /// Memory in megabytes.
+        memory_size: "memory_size" => i32,
+        /// Graphics API, e.g. `Vulkan`.
+        api_type: "api_type" => String,
+        /// Driver version.
+        driver_version: "driver_version" => String,
+    }
+}
+
+#[test]
+fn protocol() -> anyhow::Result<()> {
+    let exception = Exception {
+        ty: Some("ParseIntError".into()),
+        value: Some("invalid digit found in string".into()),
+        mechanism: Some(Mechanism {
+            ty: Some("generic".into()),
+            handled: Some(false),
+            ..Mechanism::default()
+        }),
+        stacktrace: Some(Stacktrace {
+            frames: Some(vec![Frame {
+                function: Some("main".into()),
+                lineno: Some(10),
+                in_app: Some(true),
+                other: vec![("custom".to_owned(), Value::new(1))]
+                    .into_iter()
+                    .collect(),
+                ..Frame::default()
+            }]),
+            ..Stacktrace::default()
+        }),
+        ..Exception::default()
+    };
+
+    let value = Value::from(exception.clone());
+    assert_eq!(
+        Some("ParseIntError"),
+        value
+            .as_map()
+            .and_then(|map| map.get("type"))
+            .and_then(Value::as_str)
+    );
+    assert_eq!(exception, Exception::try_from(value.clone())?);
+    assert_eq!(value, unsafe { Value::from_raw(exception.into_raw()) });
+
+    // `null` is treated like a missing field
+    let thread = Thread::try_from(Value::new(vec![
+        ("id", Value::new(1)),
+        ("name", Value::Null),
+    ]))?;
+    assert_eq!(Some("1"), thread.id.as_deref());
+    assert_eq!(None, thread.name);
+
+    // numbers are accepted as doubles too
+    let frame = Frame::try_from(Value::new(vec![("lineno", 10.)]))?;
+    assert_eq!(Some(10), frame.lineno);
+    let thread = Thread::try_from(Value::new(vec![("id", 4_294_967_296.)]))?;
+    assert_eq!(Some("4294967296"), thread.id.as_deref());
+
+    // values with the wrong type are kept in `other`
+    let value = Value::new(vec![
+        ("lineno", Value::new("ten")),
+        ("colno", Value::new(10.5)),
+        ("function", Value::new("main")),
+    ]);
+    let frame = Frame::try_from(value.clone())?;
+    assert_eq!(None, frame.lineno);
+    assert_eq!(None, frame.colno);
+    assert_eq!(Some("main"), frame.function.as_deref());
+    assert_eq!(Some(&Value::new("ten")), frame.other.get("lineno"));
+    assert_eq!(value, Value::from(frame));
+
+    // only a map can be converted
+    assert_eq!(
+        Err(Error::TryConvert(Value::new(true))),
+        Frame::try_from(Value::new(true))
+    );
+
+    let os = OsContext {
+        name: Some("Linux".into()),
+        ..OsContext::default()
+    };
+    let value = Value::from(os.clone());
+    assert_eq!(
+        Some("os"),
+        value
+            .as_map()
+            .and_then(|map| map.get("type"))
+            .and_then(Value::as_str)
+    );
+    assert_eq!(os, OsContext::try_from(value)?);
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! Recording transport and event matchers for test suites.
+
+use crate::{Options, RawEnvelope, Transport, TransportShutdown, Value};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// Events recorded by [`RecordingTransport`], notifies about new events.
+static EVENTS: Lazy<(Mutex<Vec<Value>>, Condvar)> =
+    Lazy::new(|| (Mutex::new(Vec::new()), Condvar::new()));
+
+/// [`Transport`] recording the event of every envelope instead of sending it,
+/// see [`captured_events`]. Envelopes without an event, like sessions, are
+/// ignored.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{set_tag, test_util, Event, Options};
+/// # use std::time::Duration;
+/// let mut options = Options::new();
+/// options.set_transport(test_util::RecordingTransport::new);
+/// let _shutdown = options.init()?;
+///
+/// set_tag("test", "recording");
+/// Event::new().capture();
+///
+/// let events = test_util::wait_for_events(1, Duration::from_secs(1));
+/// assert_eq!(1, events.len());
+/// assert!(test_util::has_tag(&events[0], "test", "recording"));
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub struct RecordingTransport;
+
+impl RecordingTransport {
+    /// Creates a new [`RecordingTransport`] and clears all previously captured
+    /// events. Intended to be passed to [`Options::set_transport`].
+    ///
+    /// # Errors
+    /// Never fails, the signature matches what [`Options::set_transport`]
+    /// expects.
+    #[allow(clippy::result_unit_err, clippy::unnecessary_wraps)]
+    pub fn new(_: &Options) -> Result<Self, ()> {
+        clear_events();
+        Ok(Self)
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        let event = envelope.event();
+
+        if !event.is_null() {
+            let (events, recorded) = &*EVENTS;
+            events.lock().expect("lock poisoned").push(event);
+            recorded.notify_all();
+        }
+    }
+
+    fn shutdown(self: Box<Self>, _: Duration) -> TransportShutdown {
+        TransportShutdown::Success
+    }
+
+    fn flush(&self, _: Duration) -> bool {
+        true
+    }
+}
+
+/// Returns all events recorded by [`RecordingTransport`] so far.
+#[must_use]
+#[allow(clippy::missing_panics_doc)]
+pub fn captured_events() -> Vec<Value> {
+    EVENTS.0.lock().expect("lock poisoned").clone()
+}
+
+/// Clears all events recorded by [`RecordingTransport`].
+#[allow(clippy::missing_panics_doc)]
+pub fn clear_events() {
+    EVENTS.0.lock().expect("lock poisoned").clear();
+}
+
+/// Waits until [`RecordingTransport`] recorded at least `count` events or
+/// `timeout` elapsed and returns all events recorded so far, which are fewer
+/// than `count` if it timed out.
+#[must_use]
+#[allow(clippy::missing_panics_doc)]
+pub fn wait_for_events(count: usize, timeout: Duration) -> Vec<Value> {
+    let (events, recorded) = &*EVENTS;
+    let (events, _) = recorded
+        .wait_timeout_while(events.lock().expect("lock poisoned"), timeout, |events| {
+            events.len() < count
+        })
+        .expect("lock poisoned");
+
+    events.clone()
+}
+
+/// Returns `true` if `event` has a tag `key` with the given `value`.
+#[must_use]
+pub fn has_tag(event: &Value, key: &str, value: &str) -> bool {
+    field(event, "tags")
+        .and_then(Value::as_map)
+        .and_then(|tags| tags.get(key))
+        .and_then(Value::as_str)
+        == Some(value)
+}
+
+/// Returns `true` if the user of `event` has a field `key` with the given
+/// `value`, e.g. `id` or `email`.
+#[must_use]
+pub fn has_user<V: Into<Value>>(event: &Value, key: &str, value: V) -> bool {
+    field(event, "user")
+        .and_then(Value::as_map)
+        .and_then(|user| user.get(key))
+        == Some(&value.into())
+}
+
+/// Returns `true` if `event` has a breadcrumb with the given `message`.
+#[must_use]
+pub fn has_breadcrumb(event: &Value, message: &str) -> bool {
+    breadcrumbs(event)
+        .iter()
+        .any(|breadcrumb| breadcrumb.get("message").and_then(Value::as_str) == Some(message))
+}
+
+/// Returns the breadcrumbs of `event`.
+#[must_use]
+pub fn breadcrumbs(event: &Value) -> Vec<&BTreeMap<String, Value>> {
+    // breadcrumbs can be a list or a map with the list in `values`
+    let breadcrumbs = match field(event, "breadcrumbs") {
+        Some(Value::Map(breadcrumbs)) => breadcrumbs.get("values"),
+        breadcrumbs => breadcrumbs,
+    };
+
+    breadcrumbs
+        .and_then(Value::as_list)
+        .map(|breadcrumbs| breadcrumbs.iter().filter_map(Value::as_map).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the field `key` of `event`.
+fn field<'a>(event: &'a Value, key: &str) -> Option<&'a Value> {
+    event.as_map().and_then(|event| event.get(key))
+}
+
+#[test]
+fn matchers() {
+    let event = Value::new(vec![
+        ("tags", Value::new(vec![("a", "b")])),
+        ("user", Value::new(vec![("id", Value::new(1))])),
+        (
+            "breadcrumbs",
+            Value::new(vec![Value::new(vec![("message", "test")])]),
+        ),
+    ]);
+
+    assert!(has_tag(&event, "a", "b"));
+    assert!(!has_tag(&event, "a", "c"));
+    assert!(!has_tag(&Value::Null, "a", "b"));
+    assert!(has_user(&event, "id", 1));
+    assert!(!has_user(&event, "id", "1"));
+    assert!(has_breadcrumb(&event, "test"));
+    assert!(!has_breadcrumb(&event, "other"));
+
+    let event = Value::new(vec![(
+        "breadcrumbs",
+        Value::new(vec![(
+            "values",
+            Value::new(vec![Value::new(vec![("message", "test")])]),
+        )]),
+    )]);
+    assert_eq!(1, breadcrumbs(&event).len());
+    assert!(has_breadcrumb(&event, "test"));
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn recording() -> anyhow::Result<()> {
+    use crate::{set_tag, test, Breadcrumb, Event, User};
+
+    test::set_hook();
+
+    let mut options = Options::new();
+    options.set_transport(RecordingTransport::new);
+    let shutdown = options.init()?;
+
+    set_tag("test", "recording");
+    let mut user = User::new();
+    user.insert("id", 1);
+    user.set();
+    Breadcrumb::new(None, Some("test".into())).add();
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    let events = wait_for_events(3, Duration::from_secs(10));
+    assert_eq!(3, events.len());
+    assert_eq!(events, captured_events());
+
+    for event in &events {
+        assert!(has_tag(event, "test", "recording"));
+        assert!(has_user(event, "id", 1));
+        assert!(has_breadcrumb(event, "test"));
+    }
+
+    clear_events();
+    assert!(captured_events().is_empty());
+    assert!(wait_for_events(1, Duration::from_millis(10)).is_empty());
+
+    shutdown.shutdown();
+
+    test::verify_panics();
+
+    Ok(())
+}
@@ -0,0 +1,253 @@
+//! Client-side throttling of events and sessions, see
+//! [`Options::set_throttle`].
+
+#[cfg(doc)]
+use crate::{start_session, Event, Options, Statistics};
+use crate::{statistics, DropReason};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// [`Throttle`]s of the running SDK, set by [`Options::init`] and removed by
+/// [`shutdown`](crate::shutdown).
+static THROTTLES: Lazy<Mutex<BTreeMap<ThrottleKind, Bucket>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Kind of data a [`Throttle`] applies to, see [`Options::set_throttle`].
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ThrottleKind {
+    /// Events captured with [`Event::capture`] that aren't messages, e.g.
+    /// events with an exception.
+    Error,
+    /// Events captured with [`Event::capture`] that were created with
+    /// [`Event::new_message`] or have a `message` but no `exception`.
+    Message,
+    /// Sessions started with [`start_session`].
+    Session,
+}
+
+/// Token bucket limiting how often something can happen, see
+/// [`Options::set_throttle`].
+///
+/// Up to `burst` events are allowed at once, after which `rate` events are
+/// allowed per `interval`.
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::Throttle;
+/// # use std::time::Duration;
+/// // at most 10 events per minute, but 5 of them at once
+/// let throttle = Throttle::per_minute(10, 5);
+/// assert_eq!(
+///     Throttle {
+///         rate: 10,
+///         interval: Duration::from_secs(60),
+///         burst: 5,
+///     },
+///     throttle
+/// );
+/// ```
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Throttle {
+    /// Number of events allowed per `interval`.
+    pub rate: u32,
+    /// Interval `rate` applies to.
+    pub interval: Duration,
+    /// Number of events allowed at once, at least one.
+    pub burst: u32,
+}
+
+impl Throttle {
+    /// Creates a new [`Throttle`] allowing `rate` events per minute and
+    /// `burst` events at once.
+    #[must_use]
+    pub const fn per_minute(rate: u32, burst: u32) -> Self {
+        Self {
+            rate,
+            interval: Duration::from_secs(60),
+            burst,
+        }
+    }
+}
+
+/// State of a [`Throttle`].
+#[derive(Debug)]
+struct Bucket {
+    /// Configuration.
+    throttle: Throttle,
+    /// Events currently allowed.
+    tokens: f64,
+    /// Last time `tokens` was refilled.
+    updated: Instant,
+    /// Events suppressed since the last allowed one.
+    suppressed: u64,
+}
+
+impl Bucket {
+    /// Creates a new full [`Bucket`].
+    fn new(throttle: Throttle, now: Instant) -> Self {
+        Self {
+            throttle,
+            tokens: f64::from(throttle.burst.max(1)),
+            updated: now,
+            suppressed: 0,
+        }
+    }
+
+    /// Takes a token at `now`. Returns the number of events suppressed since
+    /// the last allowed one, or [`None`] if this one is suppressed too.
+    fn acquire(&mut self, now: Instant) -> Option<u64> {
+        let burst = f64::from(self.throttle.burst.max(1));
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.updated = now;
+
+        self.tokens = if self.throttle.interval.as_nanos() == 0 {
+            burst
+        } else {
+            let refill = elapsed.as_secs_f64() * f64::from(self.throttle.rate)
+                / self.throttle.interval.as_secs_f64();
+            (self.tokens + refill).min(burst)
+        };
+
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            Some(std::mem::take(&mut self.suppressed))
+        } else {
+            self.suppressed += 1;
+            None
+        }
+    }
+}
+
+/// Sets the [`Throttle`]s of the running SDK, see [`Options::init`].
+pub(crate) fn set(throttles: &BTreeMap<ThrottleKind, Throttle>) {
+    let now = Instant::now();
+
+    *THROTTLES.lock().expect("lock poisoned") = throttles
+        .iter()
+        .map(|(kind, throttle)| (*kind, Bucket::new(*throttle, now)))
+        .collect();
+}
+
+/// Returns the [`Throttle`]s of the running SDK.
+pub(crate) fn get() -> BTreeMap<ThrottleKind, Throttle> {
+    THROTTLES
+        .lock()
+        .expect("lock poisoned")
+        .iter()
+        .map(|(kind, bucket)| (*kind, bucket.throttle))
+        .collect()
+}
+
+/// Removes all [`Throttle`]s, see [`shutdown`](crate::shutdown).
+pub(crate) fn clear() {
+    THROTTLES.lock().expect("lock poisoned").clear();
+}
+
+/// Asks the [`Throttle`] of `kind` if another event is allowed. Returns the
+/// number of events suppressed since the last allowed one, or [`None`] if
+/// this one is suppressed too, which is counted as
+/// [`DropReason::Throttled`] in [`Statistics`].
+pub(crate) fn acquire(kind: ThrottleKind) -> Option<u64> {
+    let result = THROTTLES
+        .lock()
+        .expect("lock poisoned")
+        .get_mut(&kind)
+        .map_or(Some(0), |bucket| bucket.acquire(Instant::now()));
+
+    if result.is_none() {
+        statistics().record_dropped(DropReason::Throttled);
+    }
+
+    result
+}
+
+#[test]
+fn bucket() {
+    let start = Instant::now();
+    let mut bucket = Bucket::new(
+        Throttle {
+            rate: 2,
+            interval: Duration::from_secs(1),
+            burst: 3,
+        },
+        start,
+    );
+
+    assert_eq!(Some(0), bucket.acquire(start));
+    assert_eq!(Some(0), bucket.acquire(start));
+    assert_eq!(Some(0), bucket.acquire(start));
+    assert_eq!(None, bucket.acquire(start));
+    assert_eq!(None, bucket.acquire(start + Duration::from_millis(250)));
+    // refilled one token
+    assert_eq!(Some(2), bucket.acquire(start + Duration::from_millis(500)));
+    assert_eq!(None, bucket.acquire(start + Duration::from_millis(500)));
+    // never refills more than the burst
+    let later = start + Duration::from_secs(60);
+    assert_eq!(Some(1), bucket.acquire(later));
+    assert_eq!(Some(0), bucket.acquire(later));
+    assert_eq!(Some(0), bucket.acquire(later));
+    assert_eq!(None, bucket.acquire(later));
+
+    // a burst of zero still allows a single event
+    let mut bucket = Bucket::new(Throttle::per_minute(0, 0), start);
+    assert_eq!(Some(0), bucket.acquire(start));
+    assert_eq!(None, bucket.acquire(start + Duration::from_secs(3600)));
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn throttling() -> anyhow::Result<()> {
+    use crate::{test, test_util, Level, Value};
+    use std::thread;
+
+    test::set_hook();
+
+    let mut options = Options::new();
+    options.set_transport(test_util::RecordingTransport::new);
+    options.set_throttle(
+        ThrottleKind::Error,
+        Throttle {
+            rate: 1,
+            interval: Duration::from_millis(100),
+            burst: 2,
+        },
+    );
+    let shutdown = options.init()?;
+    let statistics = shutdown.statistics();
+
+    for _ in 0..5 {
+        Event::new().capture();
+    }
+
+    // messages aren't limited by the error throttle
+    Event::new_message(Level::Info, None, "message").capture();
+
+    let events = test_util::wait_for_events(3, Duration::from_secs(1));
+    assert_eq!(3, events.len());
+    assert_eq!(3, statistics.dropped_by(DropReason::Throttled));
+
+    thread::sleep(Duration::from_millis(150));
+    Event::new().capture();
+
+    let events = test_util::wait_for_events(4, Duration::from_secs(1));
+    assert_eq!(4, events.len());
+    assert_eq!(
+        Some(3),
+        events[3]
+            .as_map()
+            .and_then(|event| event.get("extra"))
+            .and_then(Value::as_map)
+            .and_then(|extra| extra.get("suppressed_events"))
+            .and_then(Value::as_int)
+    );
+
+    shutdown.shutdown();
+
+    test::verify_panics();
+
+    Ok(())
+}
@@ -0,0 +1,400 @@
+//! Builder for envelopes assembled in Rust.
+
+use super::send_envelope;
+use crate::{
+    Dsn, Envelope, EnvelopeItem, Event, Map, Object, ParsedEnvelope, RawEnvelope, Request, Uuid,
+    Value,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Status of a monitor check-in, see [`EnvelopeBuilder::add_check_in`].
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub enum CheckInStatus {
+    /// The job started.
+    InProgress,
+    /// The job finished successfully.
+    Ok,
+    /// The job failed.
+    Error,
+}
+
+impl CheckInStatus {
+    /// Converts [`CheckInStatus`] into its protocol representation.
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::InProgress => "in_progress",
+            Self::Ok => "ok",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Assembles an envelope from items built in Rust, which can be sent through
+/// the [`Transport`](crate::Transport) configured with
+/// [`Options::set_transport`](crate::Options::set_transport) or converted
+/// into an [`Envelope`] or a [`Request`].
+///
+/// Envelopes built this way don't pass through sentry-native, so
+/// [`Options::set_before_send`](crate::Options::set_before_send), sampling
+/// and the scope, like tags or the user, don't apply to them.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::EnvelopeBuilder;
+/// let mut builder = EnvelopeBuilder::new();
+/// builder.add_attachment("log.txt", b"something happened".to_vec());
+/// builder.add_session(vec![("sid", "9ec79c33ec9942ab8353589fcb2e04dc")]);
+///
+/// let envelope = builder.into_envelope().parse()?;
+/// assert_eq!("attachment", envelope.items[0].item_type);
+/// assert_eq!("session", envelope.items[1].item_type);
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvelopeBuilder {
+    /// Envelope being built.
+    envelope: ParsedEnvelope,
+    /// Value of the `sent_at` header, defaults to the time of
+    /// [`EnvelopeBuilder::build`].
+    sent_at: Option<SystemTime>,
+}
+
+impl EnvelopeBuilder {
+    /// Creates a new empty [`EnvelopeBuilder`].
+    #[must_use = "`EnvelopeBuilder` doesn't do anything without `EnvelopeBuilder::send`"]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the `event_id` header. Otherwise it's taken from the first event
+    /// added with [`EnvelopeBuilder::add_event`].
+    pub fn set_event_id(&mut self, event_id: Uuid) {
+        self.envelope
+            .header
+            .insert("event_id".into(), event_id.to_string().into());
+    }
+
+    /// Sets the `dsn` header, which allows relays to authenticate the
+    /// envelope.
+    pub fn set_dsn<S: Into<String>>(&mut self, dsn: S) {
+        self.envelope.header.insert("dsn".into(), dsn.into().into());
+    }
+
+    /// Sets the `sent_at` header, which defaults to the time of
+    /// [`EnvelopeBuilder::build`].
+    pub fn set_sent_at(&mut self, sent_at: SystemTime) {
+        self.sent_at = Some(sent_at);
+    }
+
+    /// Adds an `event` item. Sets the `event_id` header to the ID of `event`
+    /// if it wasn't set yet.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{EnvelopeBuilder, Event};
+    /// let mut event = Event::new();
+    /// event.insert("message", "test");
+    ///
+    /// let mut builder = EnvelopeBuilder::new();
+    /// builder.add_event(event);
+    /// ```
+    pub fn add_event(&mut self, event: Event) {
+        let event = unsafe { Value::from_raw(event.into_raw()) };
+
+        if let Some(event_id) = event
+            .as_map()
+            .and_then(|event| event.get("event_id"))
+            .cloned()
+        {
+            self.envelope
+                .header
+                .entry("event_id".into())
+                .or_insert(event_id);
+        }
+
+        self.add_item(EnvelopeItem::new("event", to_json(event)));
+    }
+
+    /// Adds an `attachment` item with the given `filename`.
+    pub fn add_attachment<S: Into<String>>(&mut self, filename: S, payload: Vec<u8>) {
+        let mut item = EnvelopeItem::new("attachment", payload);
+        item.filename = Some(filename.into());
+        self.add_item(item);
+    }
+
+    /// Adds a `user_report` item with feedback about the event with the given
+    /// `event_id`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{EnvelopeBuilder, Event};
+    /// let event_id = Event::new().capture();
+    ///
+    /// let mut builder = EnvelopeBuilder::new();
+    /// builder.add_user_feedback(event_id, "John Doe", "john@example.com", "It broke!");
+    /// ```
+    pub fn add_user_feedback<S1: Into<String>, S2: Into<String>, S3: Into<String>>(
+        &mut self,
+        event_id: Uuid,
+        name: S1,
+        email: S2,
+        comments: S3,
+    ) {
+        let feedback = Value::new(vec![
+            ("event_id", event_id.to_plain()),
+            ("name", name.into()),
+            ("email", email.into()),
+            ("comments", comments.into()),
+        ]);
+
+        self.add_item(EnvelopeItem::new("user_report", to_json(feedback)));
+    }
+
+    /// Adds a `session` item with the given session update.
+    pub fn add_session<M: Map + Into<Value>>(&mut self, session: M) {
+        self.add_item(EnvelopeItem::new("session", to_json(session)));
+    }
+
+    /// Adds a `check_in` item for the monitor `monitor_slug`. Check-ins of
+    /// the same job have to use the same `check_in_id`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{CheckInStatus, EnvelopeBuilder, Uuid};
+    /// # use std::time::Duration;
+    /// let check_in_id = Uuid::new_v4();
+    ///
+    /// let mut builder = EnvelopeBuilder::new();
+    /// builder.add_check_in(
+    ///     check_in_id,
+    ///     "nightly-backup",
+    ///     CheckInStatus::InProgress,
+    ///     None,
+    /// );
+    ///
+    /// // run the job
+    ///
+    /// let mut builder = EnvelopeBuilder::new();
+    /// builder.add_check_in(
+    ///     check_in_id,
+    ///     "nightly-backup",
+    ///     CheckInStatus::Ok,
+    ///     Some(Duration::from_secs(10)),
+    /// );
+    /// ```
+    pub fn add_check_in<S: Into<String>>(
+        &mut self,
+        check_in_id: Uuid,
+        monitor_slug: S,
+        status: CheckInStatus,
+        duration: Option<Duration>,
+    ) {
+        let mut check_in = vec![
+            ("check_in_id", Value::new(check_in_id.to_plain())),
+            ("monitor_slug", Value::new(monitor_slug.into())),
+            ("status", Value::new(status.as_str())),
+        ];
+
+        if let Some(duration) = duration {
+            check_in.push(("duration", Value::new(duration.as_secs_f64())));
+        }
+
+        self.add_item(EnvelopeItem::new("check_in", to_json(check_in)));
+    }
+
+    /// Adds any other item.
+    pub fn add_item(&mut self, item: EnvelopeItem) {
+        self.envelope.items.push(item);
+    }
+
+    /// Builds the [`ParsedEnvelope`], setting the `sent_at` header.
+    #[must_use]
+    pub fn build(self) -> ParsedEnvelope {
+        let Self {
+            mut envelope,
+            sent_at,
+        } = self;
+
+        envelope.header.insert(
+            "sent_at".into(),
+            to_rfc3339(sent_at.unwrap_or_else(SystemTime::now)).into(),
+        );
+
+        envelope
+    }
+
+    /// Builds and serializes the envelope, see [`EnvelopeBuilder::build`].
+    #[must_use = "`EnvelopeBuilder::into_envelope` only converts it to an `Envelope`, this doesn't do anything until it is sent"]
+    pub fn into_envelope(self) -> Envelope {
+        self.build().into()
+    }
+
+    /// Builds the envelope and constructs a HTTP request for it with a
+    /// [`Dsn`], see [`Envelope::into_request`].
+    #[must_use = "`Request` doesn't do anything until it is sent"]
+    pub fn into_request(self, dsn: Dsn) -> Request {
+        self.into_envelope().into_request(dsn)
+    }
+
+    /// Builds the envelope and sends it with the
+    /// [`Transport`](crate::Transport) configured with
+    /// [`Options::set_transport`](crate::Options::set_transport).
+    ///
+    /// # Errors
+    /// Fails with [`Error::Transport`](crate::Error::Transport) if Sentry isn't
+    /// initialized with a custom transport.
+    ///
+    /// # Examples
+    /// ```
+    /// # fn main() -> anyhow::Result<()> {
+    /// # use sentry_contrib_native::{EnvelopeBuilder, Options, RawEnvelope};
+    /// let mut options = Options::new();
+    /// options.set_transport(|_| Ok(|envelope: RawEnvelope| println!("{:?}", envelope)));
+    /// let _shutdown = options.init()?;
+    ///
+    /// let mut builder = EnvelopeBuilder::new();
+    /// builder.add_attachment("log.txt", b"something happened".to_vec());
+    /// builder.send()?;
+    /// # Ok(()) }
+    /// ```
+    pub fn send(self) -> Result<(), crate::Error> {
+        send_envelope(RawEnvelope::from(self.into_envelope())).map_err(Into::into)
+    }
+}
+
+/// Serializes `value` to JSON.
+fn to_json<V: Into<Value>>(value: V) -> Vec<u8> {
+    serde_json::to_vec(&value.into().into_json()).expect("failed to serialize payload")
+}
+
+/// Formats `time` as an RFC 3339 timestamp in UTC with millisecond precision.
+fn to_rfc3339(time: SystemTime) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = time.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // see <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        time.subsec_millis()
+    )
+}
+
+#[test]
+fn builder() -> anyhow::Result<()> {
+    assert_eq!("1970-01-01T00:00:00.000Z", to_rfc3339(UNIX_EPOCH));
+    assert_eq!(
+        "2000-02-29T23:59:59.999Z",
+        to_rfc3339(UNIX_EPOCH + Duration::from_millis(951_868_799_999))
+    );
+    assert_eq!(
+        "2021-03-01T12:30:05.000Z",
+        to_rfc3339(UNIX_EPOCH + Duration::from_secs(1_614_601_805))
+    );
+
+    let mut builder = EnvelopeBuilder::new();
+    builder.set_dsn("https://public@example.com/1");
+    builder.set_sent_at(UNIX_EPOCH);
+    builder.add_attachment("a.txt", b"a\nb".to_vec());
+    builder.add_session(vec![("sid", "1"), ("status", "ok")]);
+    builder.add_item(EnvelopeItem::new("client_report", b"{}".to_vec()));
+
+    let envelope = builder.into_envelope().parse()?;
+    assert_eq!(
+        Some(&Value::new("https://public@example.com/1")),
+        envelope.header.get("dsn")
+    );
+    assert_eq!(
+        Some(&Value::new("1970-01-01T00:00:00.000Z")),
+        envelope.header.get("sent_at")
+    );
+    assert_eq!(None, envelope.header.get("event_id"));
+    assert_eq!(3, envelope.items.len());
+
+    let attachment = &envelope.items[0];
+    assert_eq!("attachment", attachment.item_type);
+    assert_eq!(Some("a.txt"), attachment.filename.as_deref());
+    assert_eq!(b"a\nb", attachment.payload.as_slice());
+
+    assert_eq!(
+        Some(Value::new(vec![("sid", "1"), ("status", "ok")])),
+        envelope.items[1].payload_value()
+    );
+    assert_eq!("client_report", envelope.items[2].item_type);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn send() -> anyhow::Result<()> {
+    use crate::{test, Options, TransportError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SEND: AtomicUsize = AtomicUsize::new(0);
+
+    test::set_hook();
+
+    assert_eq!(
+        Err(crate::Error::Transport(TransportError::NoTransport)),
+        EnvelopeBuilder::new().send()
+    );
+
+    let mut options = Options::new();
+    options.set_transport(|_| {
+        Ok(|envelope: RawEnvelope| {
+            let envelope = envelope.serialize().parse().unwrap();
+
+            if envelope
+                .items
+                .iter()
+                .any(|item| item.item_type == "check_in")
+            {
+                assert!(envelope.header.contains_key("event_id"));
+                assert_eq!(3, envelope.items.len());
+                SEND.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+    });
+    let shutdown = options.init()?;
+
+    let mut event = Event::new();
+    event.insert("message", "test");
+    let event_id = Event::new().capture();
+
+    let mut builder = EnvelopeBuilder::new();
+    builder.add_event(event);
+    builder.add_user_feedback(event_id, "name", "name@example.com", "comments");
+    builder.add_check_in(Uuid::new_v4(), "test", CheckInStatus::Ok, None);
+    builder.send()?;
+
+    shutdown.shutdown();
+
+    assert_eq!(1, SEND.load(Ordering::SeqCst));
+    assert_eq!(
+        Err(crate::Error::Transport(TransportError::NoTransport)),
+        EnvelopeBuilder::new().send()
+    );
+
+    test::verify_panics();
+
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Compression of request bodies.
+
+#[cfg(feature = "compression-gzip")]
+use ::flate2::{write::GzEncoder, Compression as GzLevel};
+#[cfg(feature = "compression-gzip")]
+use std::io::Write;
+
+/// Content coding used by [`Compression`].
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// Don't compress.
+    Identity,
+    /// Compress with gzip, requires the `compression-gzip` feature.
+    #[cfg(feature = "compression-gzip")]
+    Gzip,
+    /// Compress with Zstandard, requires the `compression-zstd` feature.
+    #[cfg(feature = "compression-zstd")]
+    Zstd,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Identity
+    }
+}
+
+impl Encoding {
+    /// Value of the `Content-Encoding` header, [`None`] for
+    /// [`Encoding::Identity`].
+    #[must_use]
+    pub const fn as_header(self) -> Option<&'static str> {
+        match self {
+            Self::Identity => None,
+            #[cfg(feature = "compression-gzip")]
+            Self::Gzip => Some("gzip"),
+            #[cfg(feature = "compression-zstd")]
+            Self::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// Configures the compression of request bodies, see
+/// [`Envelope::into_compressed_request`](crate::Envelope::into_compressed_request).
+///
+/// Bodies smaller than the threshold are sent uncompressed, as compressing
+/// them isn't worth the overhead.
+///
+/// # Examples
+/// ```
+/// # #[cfg(feature = "compression-gzip")]
+/// # {
+/// # use sentry_contrib_native::{Compression, Encoding};
+/// // compress everything bigger than 4 KiB with gzip
+/// let compression = Compression::with_threshold(Encoding::Gzip, 4096);
+/// assert_eq!(None, compression.compress(b"too small to be compressed"));
+/// # }
+/// ```
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Compression {
+    /// Content coding to use.
+    encoding: Encoding,
+    /// Minimum size of bodies in bytes to be compressed.
+    threshold: usize,
+}
+
+impl Compression {
+    /// Default minimum size of bodies in bytes to be compressed.
+    pub const DEFAULT_THRESHOLD: usize = 1024;
+
+    /// Creates a new [`Compression`] with the given `encoding` and
+    /// [`Compression::DEFAULT_THRESHOLD`].
+    #[must_use]
+    pub const fn new(encoding: Encoding) -> Self {
+        Self::with_threshold(encoding, Self::DEFAULT_THRESHOLD)
+    }
+
+    /// Creates a new [`Compression`] with the given `encoding`, compressing
+    /// only bodies of at least `threshold` bytes.
+    #[must_use]
+    pub const fn with_threshold(encoding: Encoding, threshold: usize) -> Self {
+        Self {
+            encoding,
+            threshold,
+        }
+    }
+
+    /// Content coding used.
+    #[must_use]
+    pub const fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Minimum size of bodies in bytes to be compressed.
+    #[must_use]
+    pub const fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Compresses `body`. Returns [`None`] if `body` is smaller than the
+    /// threshold, compression is disabled or failed, in which case `body`
+    /// should be sent uncompressed.
+    #[must_use]
+    pub fn compress(&self, body: &[u8]) -> Option<Vec<u8>> {
+        if body.len() < self.threshold {
+            return None;
+        }
+
+        match self.encoding {
+            Encoding::Identity => None,
+            #[cfg(feature = "compression-gzip")]
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+                encoder.write_all(body).ok()?;
+                encoder.finish().ok()
+            }
+            #[cfg(feature = "compression-zstd")]
+            Encoding::Zstd => zstd::encode_all(body, 0).ok(),
+        }
+    }
+}
+
+#[test]
+fn compression() {
+    use crate::{Dsn, Envelope};
+
+    let body = vec![b'a'; 2048];
+    let dsn = Dsn::new("https://public@example.com/1").unwrap();
+
+    assert_eq!(None, Compression::default().compress(&body));
+    assert_eq!(None, Compression::new(Encoding::Identity).compress(&body));
+
+    let request =
+        Envelope::from(body.clone()).into_compressed_request(dsn.clone(), Compression::default());
+    assert_eq!(None, request.headers().get("content-encoding"));
+    assert_eq!(body, request.body().as_bytes());
+
+    #[cfg(feature = "compression-gzip")]
+    {
+        use ::flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let compression = Compression::new(Encoding::Gzip);
+        assert_eq!(None, compression.compress(&body[..1023]));
+
+        let compressed = compression.compress(&body).unwrap();
+        assert!(compressed.len() < body.len());
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(body, decompressed);
+
+        let request = Envelope::from(body.clone()).into_compressed_request(dsn, compression);
+        assert_eq!("gzip", request.headers().get("content-encoding").unwrap());
+        assert_eq!(
+            &compressed.len().to_string(),
+            request.headers().get("content-length").unwrap()
+        );
+        assert_eq!(compressed, request.body().as_bytes());
+    }
+
+    #[cfg(feature = "compression-zstd")]
+    {
+        let compression = Compression::with_threshold(Encoding::Zstd, 0);
+        let compressed = compression.compress(b"a").unwrap();
+        assert_eq!(
+            b"a",
+            zstd::decode_all(compressed.as_slice()).unwrap().as_slice()
+        );
+
+        let compressed = compression.compress(&body).unwrap();
+        assert!(compressed.len() < body.len());
+        assert_eq!(body, zstd::decode_all(compressed.as_slice()).unwrap());
+    }
+}
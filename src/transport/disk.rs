@@ -0,0 +1,392 @@
+//! Persistent queue keeping envelopes on disk until they were delivered.
+
+use crate::{Envelope, Options, RawEnvelope, Transport, TransportShutdown};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    ffi::OsStr,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// [`Transport`] wrapping another [`Transport`], which writes every envelope to
+/// a directory before passing it on and deletes it again after it was
+/// delivered.
+///
+/// Envelopes left on disk, because the process exited before they were
+/// delivered or their delivery failed, are passed on again when the
+/// [`DiskQueue`] is created on the next [`Options::init`], before any new
+/// envelopes.
+///
+/// To find out if an envelope was delivered, the wrapped [`Transport`] has to
+/// report failures with [`RawEnvelope::report_failure`], which the shipped
+/// transports do. Envelopes that are dropped without a reported failure are
+/// considered delivered.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{DiskQueue, Event, Options, RawEnvelope};
+/// let mut options = Options::new();
+/// options.set_database_path(".sentry-native");
+/// options.set_transport(|options| {
+///     DiskQueue::new(
+///         |envelope: RawEnvelope| {
+///             // send the envelope, report failures with `RawEnvelope::report_failure`
+///         },
+///         options,
+///     )
+/// });
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+pub struct DiskQueue {
+    /// The wrapped [`Transport`].
+    transport: Box<dyn Transport>,
+    /// Envelopes stored on disk, shared with the delivery callbacks.
+    store: Arc<Mutex<Store>>,
+    /// Print errors to stderr.
+    debug: bool,
+}
+
+impl Debug for DiskQueue {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("DiskQueue")
+            .field("store", &self.store)
+            .field("debug", &self.debug)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DiskQueue {
+    /// Name of the directory inside [`Options::database_path`] used by
+    /// [`DiskQueue::new`].
+    pub const DIRECTORY: &'static str = "offline-queue";
+    /// Default maximum number of envelopes kept on disk, the oldest envelopes
+    /// are deleted first.
+    pub const MAX_COUNT: usize = 100;
+    /// Default maximum size in bytes of all envelopes kept on disk, the oldest
+    /// envelopes are deleted first.
+    pub const MAX_SIZE: u64 = 20 * 1024 * 1024;
+
+    /// Creates a new [`DiskQueue`] wrapping `transport`, storing envelopes in
+    /// [`DiskQueue::DIRECTORY`] inside [`Options::database_path`]. Keeps at
+    /// most [`DiskQueue::MAX_COUNT`] envelopes and [`DiskQueue::MAX_SIZE`]
+    /// bytes.
+    ///
+    /// Pending envelopes from previous runs are passed on to `transport`
+    /// right away.
+    ///
+    /// If [`Options::debug`] is enabled, envelopes that couldn't be written to
+    /// disk are printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if the directory couldn't be created or read.
+    #[allow(clippy::result_unit_err)]
+    pub fn new<T: Transport>(transport: T, options: &Options) -> Result<Self, ()> {
+        let dir = options
+            .database_path()
+            .unwrap_or_else(|| Path::new(".sentry-native"))
+            .join(Self::DIRECTORY);
+
+        Self::with_limits(transport, dir, Self::MAX_COUNT, Self::MAX_SIZE, options)
+    }
+
+    /// Creates a new [`DiskQueue`] like [`DiskQueue::new`], storing envelopes
+    /// in `dir` and keeping at most `max_count` envelopes and `max_size`
+    /// bytes. Envelopes bigger than `max_size` are passed on without being
+    /// stored.
+    ///
+    /// # Errors
+    /// Fails if the directory couldn't be created or read.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_limits<T: Transport, P: Into<PathBuf>>(
+        transport: T,
+        dir: P,
+        max_count: usize,
+        max_size: u64,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        let (store, pending) = Store::open(dir.into(), max_count, max_size).map_err(|_| ())?;
+
+        let queue = Self {
+            transport: Box::new(transport),
+            store: Arc::new(Mutex::new(store)),
+            debug: options.debug(),
+        };
+
+        for (sequence, envelope) in pending {
+            let mut envelope = RawEnvelope::from(Envelope::from(envelope));
+            queue.on_delivery(&mut envelope, sequence);
+            queue.transport.send(envelope);
+        }
+
+        Ok(queue)
+    }
+
+    /// Deletes the envelope with the given `sequence` number from disk once
+    /// `envelope` was delivered.
+    fn on_delivery(&self, envelope: &mut RawEnvelope, sequence: u64) {
+        let store = Arc::clone(&self.store);
+
+        envelope.on_delivery(move |delivered| {
+            if delivered {
+                store.lock().expect("lock poisoned").remove(sequence);
+            }
+        });
+    }
+}
+
+impl Transport for DiskQueue {
+    fn send(&self, mut envelope: RawEnvelope) {
+        let result = self
+            .store
+            .lock()
+            .expect("lock poisoned")
+            .push(envelope.serialize().as_bytes());
+
+        match result {
+            Ok(Some(sequence)) => self.on_delivery(&mut envelope, sequence),
+            Ok(None) => (),
+            Err(error) => {
+                if self.debug {
+                    eprintln!(
+                        "[sentry-contrib-native] failed to write envelope to disk: {}",
+                        error
+                    );
+                }
+            }
+        }
+
+        self.transport.send(envelope);
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        self.transport.shutdown(timeout)
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        self.transport.flush(timeout)
+    }
+}
+
+/// Pending envelopes with their sequence number, see [`Store::open`].
+type Pending = Vec<(u64, Vec<u8>)>;
+
+/// Envelopes stored on disk, named after their sequence number.
+#[derive(Debug)]
+struct Store {
+    /// Directory the envelopes are stored in.
+    dir: PathBuf,
+    /// Maximum number of envelopes.
+    max_count: usize,
+    /// Maximum size in bytes of all envelopes.
+    max_size: u64,
+    /// Size in bytes of every stored envelope by sequence number.
+    files: BTreeMap<u64, u64>,
+    /// Size in bytes of all stored envelopes.
+    size: u64,
+    /// Sequence number of the next envelope.
+    next: u64,
+}
+
+impl Store {
+    /// Opens the [`Store`] in `dir`, creating it if it doesn't exist. Returns
+    /// the pending envelopes in the order they were stored.
+    fn open(dir: PathBuf, max_count: usize, max_size: u64) -> io::Result<(Self, Pending)> {
+        fs::create_dir_all(&dir)?;
+
+        let mut store = Self {
+            dir,
+            max_count,
+            max_size,
+            files: BTreeMap::new(),
+            size: 0,
+            next: 0,
+        };
+        let mut pending = BTreeMap::new();
+
+        for entry in fs::read_dir(&store.dir)? {
+            let path = entry?.path();
+
+            match path.extension().and_then(OsStr::to_str) {
+                // left over from an interrupted write
+                Some("tmp") => {
+                    let _result = fs::remove_file(&path);
+                }
+                Some("envelope") => {
+                    if let Some(sequence) = path
+                        .file_stem()
+                        .and_then(OsStr::to_str)
+                        .and_then(|stem| stem.parse::<u64>().ok())
+                    {
+                        if let Ok(envelope) = fs::read(&path) {
+                            pending.insert(sequence, envelope);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        store.next = pending
+            .keys()
+            .next_back()
+            .map_or(0, |sequence| sequence + 1);
+
+        for (sequence, envelope) in &pending {
+            store.insert(*sequence, Self::len(envelope));
+        }
+
+        // only keep envelopes that weren't evicted
+        let pending = pending
+            .into_iter()
+            .filter(|(sequence, _)| store.files.contains_key(sequence))
+            .collect();
+
+        Ok((store, pending))
+    }
+
+    /// Writes `envelope` to disk. Returns its sequence number or [`None`] if
+    /// it's bigger than [`Store::max_size`].
+    fn push(&mut self, envelope: &[u8]) -> io::Result<Option<u64>> {
+        let size = Self::len(envelope);
+
+        if self.max_count == 0 || size > self.max_size {
+            return Ok(None);
+        }
+
+        let sequence = self.next;
+        self.next += 1;
+
+        // write to a temporary file first, so no partial envelopes are replayed
+        let path = self.path(sequence);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, envelope)?;
+        fs::rename(&temp, &path)?;
+
+        self.insert(sequence, size);
+
+        Ok(Some(sequence))
+    }
+
+    /// Registers a stored envelope, deleting the oldest envelopes if the
+    /// limits are exceeded.
+    fn insert(&mut self, sequence: u64, size: u64) {
+        self.files.insert(sequence, size);
+        self.size += size;
+
+        while self.files.len() > self.max_count || self.size > self.max_size {
+            if let Some(oldest) = self.files.keys().next().copied() {
+                self.remove(oldest);
+            }
+        }
+    }
+
+    /// Deletes the envelope with the given `sequence` number.
+    fn remove(&mut self, sequence: u64) {
+        if let Some(size) = self.files.remove(&sequence) {
+            self.size -= size;
+            let _result = fs::remove_file(self.path(sequence));
+        }
+    }
+
+    /// Path of the envelope with the given `sequence` number.
+    fn path(&self, sequence: u64) -> PathBuf {
+        // padded so they are sorted correctly when listed
+        self.dir.join(format!("{:020}.envelope", sequence))
+    }
+
+    /// Size of `envelope` in bytes.
+    fn len(envelope: &[u8]) -> u64 {
+        u64::try_from(envelope.len()).unwrap_or(u64::MAX)
+    }
+}
+
+#[test]
+fn store() -> anyhow::Result<()> {
+    use std::{env, process};
+
+    let dir = env::temp_dir().join(format!("sentry-contrib-native-store-{}", process::id()));
+    let _result = fs::remove_dir_all(&dir);
+
+    let (mut store, pending) = Store::open(dir.clone(), 3, 10)?;
+    assert!(pending.is_empty());
+
+    assert_eq!(Some(0), store.push(b"0000")?);
+    assert_eq!(Some(1), store.push(b"1111")?);
+    // too big
+    assert_eq!(None, store.push(b"22222222222")?);
+    // evicts the oldest envelope because of the size limit
+    assert_eq!(Some(2), store.push(b"333")?);
+    assert!(!store.path(0).exists());
+    // evicts the oldest envelope because of the count limit
+    assert_eq!(Some(3), store.push(b"4")?);
+    assert_eq!(Some(4), store.push(b"5")?);
+    assert!(!store.path(1).exists());
+    // delivered
+    store.remove(3);
+    assert!(!store.path(3).exists());
+
+    fs::write(dir.join("00000000000000000005.tmp"), b"partial")?;
+    fs::write(dir.join("unrelated.txt"), b"unrelated")?;
+
+    let (mut store, pending) = Store::open(dir.clone(), 3, 10)?;
+    assert_eq!(vec![(2, b"333".to_vec()), (4, b"5".to_vec())], pending);
+    assert!(!dir.join("00000000000000000005.tmp").exists());
+    assert!(dir.join("unrelated.txt").exists());
+    assert_eq!(Some(5), store.push(b"6")?);
+
+    // limits are applied to pending envelopes
+    let (_, pending) = Store::open(dir.clone(), 1, 10)?;
+    assert_eq!(vec![(5, b"6".to_vec())], pending);
+
+    fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn disk_queue() -> anyhow::Result<()> {
+    use crate::{test, Event};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SEND: AtomicUsize = AtomicUsize::new(0);
+
+    test::set_hook();
+
+    let mut options = Options::new();
+    options.set_transport(|options| {
+        DiskQueue::new(
+            |envelope: RawEnvelope| {
+                // fail every second envelope
+                if SEND.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                    envelope.report_failure();
+                }
+            },
+            options,
+        )
+    });
+    let dir = options.database_path().unwrap().join(DiskQueue::DIRECTORY);
+    let _result = fs::remove_dir_all(&dir);
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    assert_eq!(3, SEND.load(Ordering::SeqCst));
+    assert_eq!(2, fs::read_dir(&dir)?.count());
+
+    test::verify_panics();
+
+    Ok(())
+}
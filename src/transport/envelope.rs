@@ -0,0 +1,427 @@
+//! Typed representation of serialized envelopes.
+
+use super::Error;
+use crate::{value::MAX_EXACT_INTEGER, Category, Envelope, Json, Value};
+use ::{
+    serde_json::{Map as JsonMap, Value as JsonValue},
+    std::{collections::BTreeMap, convert::TryFrom},
+};
+
+/// Header of an envelope or an item.
+type JsonHeader = JsonMap<String, JsonValue>;
+/// Headers and payloads of items, see [`split`].
+type Items<'a> = Vec<(JsonHeader, &'a [u8])>;
+
+/// [`Envelope`] split into its header and items, see [`Envelope::parse`].
+///
+/// Can be converted back into an [`Envelope`] to be sent.
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::{Envelope, EnvelopeItem, ParsedEnvelope, RawEnvelope, Transport};
+/// struct CustomTransport;
+///
+/// impl Transport for CustomTransport {
+///     fn send(&self, raw_envelope: RawEnvelope) {
+///         let mut envelope = match raw_envelope.serialize().parse() {
+///             Ok(envelope) => envelope,
+///             Err(_) => return,
+///         };
+///
+///         // don't send any attachments
+///         envelope.items.retain(|item| item.item_type != "attachment");
+///
+///         let envelope = Envelope::from(envelope);
+///         // send it!
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParsedEnvelope {
+    /// Header of the envelope, e.g. `event_id`, `dsn` or `sent_at`.
+    pub header: BTreeMap<String, Value>,
+    /// Items of the envelope.
+    pub items: Vec<EnvelopeItem>,
+}
+
+/// Item of a [`ParsedEnvelope`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvelopeItem {
+    /// Type of the item, e.g. `event`, `session` or `attachment`.
+    pub item_type: String,
+    /// MIME type of the payload.
+    pub content_type: Option<String>,
+    /// File name of an attachment.
+    pub filename: Option<String>,
+    /// Any other headers, e.g. `attachment_type`, which is `event.minidump`
+    /// for minidumps.
+    pub headers: BTreeMap<String, Value>,
+    /// Payload of the item.
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Parses this envelope into its header and items.
+    ///
+    /// # Errors
+    /// Fails with [`Error::Transport`](crate::Error::Transport) if the envelope
+    /// is malformed or a header contains an integer that a [`Value`] can't
+    /// represent exactly, which is the case above 2^53.
+    ///
+    /// # Examples
+    /// ```
+    /// # fn main() -> anyhow::Result<()> {
+    /// # use sentry_contrib_native::{Envelope, Value};
+    /// let envelope = Envelope::from(
+    ///     b"{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\"}\n\
+    ///     {\"type\":\"attachment\",\"length\":5,\"filename\":\"a.txt\"}\n\
+    ///     hello\n"
+    ///         .to_vec(),
+    /// );
+    /// let parsed = envelope.parse()?;
+    ///
+    /// assert_eq!(
+    ///     Some(&Value::new("9ec79c33ec9942ab8353589fcb2e04dc")),
+    ///     parsed.header.get("event_id")
+    /// );
+    /// assert_eq!("attachment", parsed.items[0].item_type);
+    /// assert_eq!(Some("a.txt"), parsed.items[0].filename.as_deref());
+    /// assert_eq!(b"hello", parsed.items[0].payload.as_slice());
+    /// # Ok(()) }
+    /// ```
+    pub fn parse(&self) -> Result<ParsedEnvelope, crate::Error> {
+        let (header, items) = split(self.as_bytes())?;
+
+        Ok(ParsedEnvelope {
+            header: into_map(header).ok_or(Error::EnvelopeHeader)?,
+            items: items
+                .into_iter()
+                .map(|(mut header, payload)| {
+                    let mut take = |key| match header.remove(key) {
+                        Some(JsonValue::String(value)) => Some(value),
+                        _ => None,
+                    };
+
+                    let item_type = take("type").unwrap_or_default();
+                    let content_type = take("content_type");
+                    let filename = take("filename");
+                    header.remove("length");
+
+                    Ok(EnvelopeItem {
+                        item_type,
+                        content_type,
+                        filename,
+                        headers: into_map(header).ok_or(Error::EnvelopeItem)?,
+                        payload: payload.to_vec(),
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+impl From<ParsedEnvelope> for Envelope {
+    fn from(envelope: ParsedEnvelope) -> Self {
+        envelope.to_bytes().into()
+    }
+}
+
+impl ParsedEnvelope {
+    /// Serializes this envelope. Item headers are written with a `length`
+    /// matching their payload.
+    #[must_use]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = to_json(self.header.clone());
+        bytes.push(b'\n');
+
+        for item in &self.items {
+            let mut header = item.to_header();
+            header.insert("length".into(), item.payload.len().into());
+
+            bytes.extend(serde_json::to_vec(&header).expect("failed to serialize item header"));
+            bytes.push(b'\n');
+            bytes.extend(&item.payload);
+            bytes.push(b'\n');
+        }
+
+        bytes
+    }
+}
+
+impl EnvelopeItem {
+    /// Creates a new [`EnvelopeItem`] with the given `item_type` and
+    /// `payload`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::EnvelopeItem;
+    /// let item = EnvelopeItem::new("attachment", b"hello".to_vec());
+    /// assert_eq!(5, item.length());
+    /// ```
+    pub fn new<S: Into<String>>(item_type: S, payload: Vec<u8>) -> Self {
+        Self {
+            item_type: item_type.into(),
+            payload,
+            ..Self::default()
+        }
+    }
+
+    /// Length of the payload in bytes.
+    #[must_use]
+    pub fn length(&self) -> usize {
+        self.payload.len()
+    }
+
+    /// Rate limiting [`Category`] of this item, see
+    /// [`Category::from_item_type`].
+    #[must_use]
+    pub fn category(&self) -> Option<Category> {
+        Category::from_item_type(&self.item_type)
+    }
+
+    /// Builds the JSON header of this item, without `length`.
+    fn to_header(&self) -> JsonHeader {
+        let mut header: JsonHeader = self
+            .headers
+            .clone()
+            .into_iter()
+            .map(|(key, value)| (key, value.into_json()))
+            .collect();
+
+        header.insert("type".into(), self.item_type.clone().into());
+
+        if let Some(content_type) = &self.content_type {
+            header.insert("content_type".into(), content_type.clone().into());
+        }
+
+        if let Some(filename) = &self.filename {
+            header.insert("filename".into(), filename.clone().into());
+        }
+
+        header
+    }
+
+    /// Parses the payload as JSON, e.g. of `event` or `session` items.
+    #[must_use]
+    pub fn payload_value(&self) -> Option<Value> {
+        serde_json::from_slice::<JsonValue>(&self.payload)
+            .ok()
+            .map(Json::into_value)
+    }
+}
+
+/// Splits a serialized envelope into its header and the headers and payloads
+/// of its items.
+///
+/// # Errors
+/// Fails if the envelope is malformed.
+pub(crate) fn split(envelope: &[u8]) -> Result<(JsonHeader, Items<'_>), Error> {
+    let (header, mut rest) = line(envelope);
+    let header = serde_json::from_slice(header).map_err(|_| Error::EnvelopeHeader)?;
+    let mut items = Vec::new();
+
+    while !rest.is_empty() {
+        let (item_header, remaining) = line(rest);
+        let item_header: JsonHeader =
+            serde_json::from_slice(item_header).map_err(|_| Error::EnvelopeItem)?;
+
+        if !matches!(item_header.get("type"), Some(JsonValue::String(_))) {
+            return Err(Error::EnvelopeItem);
+        }
+
+        let payload = if let Some(length) = item_header.get("length") {
+            let length = length
+                .as_u64()
+                .and_then(|length| usize::try_from(length).ok())
+                .ok_or(Error::EnvelopeItem)?;
+            let payload = remaining.get(..length).ok_or(Error::EnvelopeItem)?;
+            let remaining = &remaining[length..];
+            rest = remaining.strip_prefix(b"\n").unwrap_or(remaining);
+
+            payload
+        } else {
+            // without a length the payload is terminated by a newline
+            let (payload, remaining) = line(remaining);
+            rest = remaining;
+
+            payload
+        };
+
+        items.push((item_header, payload));
+    }
+
+    Ok((header, items))
+}
+
+/// Serializes an envelope from its header and the headers and payloads of its
+/// items, as returned by [`split`]. Item headers are written with a `length`
+/// matching their payload.
+pub(crate) fn join(header: &JsonHeader, items: &[(JsonHeader, &[u8])]) -> Vec<u8> {
+    let mut bytes = serde_json::to_vec(header).expect("failed to serialize envelope header");
+    bytes.push(b'\n');
+
+    for (item_header, payload) in items {
+        let mut item_header = item_header.clone();
+        item_header.insert("length".into(), payload.len().into());
+
+        bytes.extend(serde_json::to_vec(&item_header).expect("failed to serialize item header"));
+        bytes.push(b'\n');
+        bytes.extend_from_slice(payload);
+        bytes.push(b'\n');
+    }
+
+    bytes
+}
+
+/// Sets `key` in the header of a serialized envelope to `value`, leaving the
+/// items untouched.
+///
+/// # Errors
+/// Fails if the envelope header is malformed.
+pub(crate) fn set_header(envelope: &[u8], key: &str, value: JsonValue) -> Result<Vec<u8>, Error> {
+    let (header, items) = line(envelope);
+    let mut header: JsonHeader =
+        serde_json::from_slice(header).map_err(|_| Error::EnvelopeHeader)?;
+    header.insert(key.into(), value);
+
+    let mut envelope = serde_json::to_vec(&header).expect("failed to serialize envelope header");
+    envelope.push(b'\n');
+    envelope.extend_from_slice(items);
+
+    Ok(envelope)
+}
+
+/// Splits `bytes` at the first newline, which is removed.
+fn line(bytes: &[u8]) -> (&[u8], &[u8]) {
+    match bytes.iter().position(|byte| *byte == b'\n') {
+        Some(position) => (&bytes[..position], &bytes[position + 1..]),
+        None => (bytes, &[]),
+    }
+}
+
+/// Converts a JSON header into a [`BTreeMap`] of [`Value`]s. Returns [`None`]
+/// if it contains integers that [`Value`] can't represent exactly, instead of
+/// rounding them.
+fn into_map(header: JsonHeader) -> Option<BTreeMap<String, Value>> {
+    if header.values().all(is_exact) {
+        Some(
+            header
+                .into_iter()
+                .map(|(key, value)| (key, value.into_value()))
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+/// Returns `true` if all integers in `value` can be represented exactly by a
+/// [`Value`], integers outside the [`i32`] range are stored as
+/// [`Value::Double`].
+#[allow(clippy::cast_precision_loss)]
+fn is_exact(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Number(number) => {
+            number.is_f64()
+                || number.as_i64().map_or(false, |number| {
+                    number.unsigned_abs() as f64 <= MAX_EXACT_INTEGER
+                })
+        }
+        JsonValue::Array(values) => values.iter().all(is_exact),
+        JsonValue::Object(map) => map.values().all(is_exact),
+        _ => true,
+    }
+}
+
+/// Serializes a header of [`Value`]s to JSON.
+fn to_json(header: BTreeMap<String, Value>) -> Vec<u8> {
+    let header: JsonHeader = header
+        .into_iter()
+        .map(|(key, value)| (key, value.into_json()))
+        .collect();
+
+    serde_json::to_vec(&header).expect("failed to serialize envelope header")
+}
+
+#[test]
+fn parse() -> anyhow::Result<()> {
+    let bytes = b"{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\"}\n\
+        {\"type\":\"event\",\"length\":13}\n\
+        {\"message\":1}\n\
+        {\"attachment_type\":\"event.minidump\",\"filename\":\"a.dmp\",\"length\":3,\"type\":\"attachment\"}\n\
+        a\nb\n\
+        {\"type\":\"session\"}\n\
+        {\"sid\":\"1\"}";
+
+    let parsed = Envelope::from(bytes.to_vec()).parse()?;
+    assert_eq!(
+        Some(&Value::new("9ec79c33ec9942ab8353589fcb2e04dc")),
+        parsed.header.get("event_id")
+    );
+    assert_eq!(3, parsed.items.len());
+
+    let event = &parsed.items[0];
+    assert_eq!("event", event.item_type);
+    assert_eq!(13, event.length());
+    assert_eq!(
+        Some(Value::new(vec![("message", 1)])),
+        event.payload_value()
+    );
+    assert_eq!(Some(Category::Error), event.category());
+
+    let attachment = &parsed.items[1];
+    assert_eq!("attachment", attachment.item_type);
+    assert_eq!(Some("a.dmp"), attachment.filename.as_deref());
+    assert_eq!(None, attachment.content_type);
+    assert_eq!(
+        Some(&Value::new("event.minidump")),
+        attachment.headers.get("attachment_type")
+    );
+    assert_eq!(b"a\nb", attachment.payload.as_slice());
+
+    let session = &parsed.items[2];
+    assert_eq!("session", session.item_type);
+    assert_eq!(b"{\"sid\":\"1\"}", session.payload.as_slice());
+
+    // round-trip
+    let envelope = Envelope::from(parsed.clone());
+    assert_eq!(parsed.to_bytes(), envelope.as_bytes());
+    assert_eq!(parsed, envelope.parse()?);
+
+    assert_eq!(
+        Ok(ParsedEnvelope::default()),
+        Envelope::from(b"{}".to_vec()).parse()
+    );
+    assert_eq!(
+        Err(crate::Error::Transport(Error::EnvelopeHeader)),
+        Envelope::from(b"".to_vec()).parse()
+    );
+    assert_eq!(
+        Err(crate::Error::Transport(Error::EnvelopeItem)),
+        Envelope::from(b"{}\n{\"length\":2}\n{}".to_vec()).parse()
+    );
+    assert_eq!(
+        Err(crate::Error::Transport(Error::EnvelopeItem)),
+        Envelope::from(b"{}\n{\"type\":\"event\",\"length\":100}\n{}".to_vec()).parse()
+    );
+
+    // large integers are kept as they are instead of being rounded
+    let envelope = Envelope::from(b"{\"sent_ms\":1700000000000}\n".to_vec());
+    let parsed = envelope.parse()?;
+    assert_eq!(
+        Some(&Value::Double(1_700_000_000_000.)),
+        parsed.header.get("sent_ms")
+    );
+    assert_eq!(envelope.as_bytes(), parsed.to_bytes().as_slice());
+    assert_eq!(
+        Err(crate::Error::Transport(Error::EnvelopeHeader)),
+        Envelope::from(b"{\"size\":9007199254740993}".to_vec()).parse()
+    );
+    assert_eq!(
+        Err(crate::Error::Transport(Error::EnvelopeItem)),
+        Envelope::from(b"{}\n{\"type\":\"event\",\"size\":-9007199254740993}\n{}".to_vec()).parse()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,363 @@
+//! Transport writing envelopes to files instead of sending them.
+
+use super::envelope::split;
+use crate::{statistics, DropReason, Options, RawEnvelope, Transport, TransportShutdown, Uuid};
+use ::{
+    serde_json::Value as JsonValue,
+    std::{
+        collections::VecDeque,
+        convert::TryFrom,
+        ffi::OsStr,
+        fs, io,
+        path::PathBuf,
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    },
+};
+
+/// [`Transport`] writing every envelope as a `.envelope` file into a
+/// directory instead of sending it, e.g. for installations without network
+/// access, where the files are collected and sent later with a separate tool.
+///
+/// Files are named after the event ID of the envelope, or a random UUID if it
+/// has none, and appear atomically: they are written to a `.tmp` file first,
+/// which is then renamed. If the configured limits are exceeded, the oldest
+/// files are deleted first. Files removed by other tools are taken into
+/// account.
+///
+/// No DSN is required.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{Event, FileTransport, Options};
+/// let mut options = Options::new();
+/// options.set_transport(|options| FileTransport::new("crash-reports", options));
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct FileTransport {
+    /// Directory the envelopes are written to.
+    directory: Mutex<Directory>,
+    /// Print errors to stderr.
+    debug: bool,
+}
+
+impl FileTransport {
+    /// Default maximum number of files kept in the directory.
+    pub const MAX_COUNT: usize = 1000;
+    /// Default maximum size in bytes of all files kept in the directory.
+    pub const MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+    /// Creates a new [`FileTransport`] writing envelopes into `dir`, keeping
+    /// at most [`FileTransport::MAX_COUNT`] files and
+    /// [`FileTransport::MAX_SIZE`] bytes.
+    ///
+    /// If [`Options::debug`] is enabled, envelopes that couldn't be written
+    /// are printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if the directory couldn't be created or read.
+    #[allow(clippy::result_unit_err)]
+    pub fn new<P: Into<PathBuf>>(dir: P, options: &Options) -> Result<Self, ()> {
+        Self::with_limits(dir, Self::MAX_COUNT, Self::MAX_SIZE, options)
+    }
+
+    /// Creates a new [`FileTransport`] like [`FileTransport::new`], keeping at
+    /// most `max_count` files and `max_size` bytes. Envelopes bigger than
+    /// `max_size` are dropped.
+    ///
+    /// # Errors
+    /// Fails if the directory couldn't be created or read.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_limits<P: Into<PathBuf>>(
+        dir: P,
+        max_count: usize,
+        max_size: u64,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        let directory = Directory::open(dir.into(), max_count, max_size).map_err(|_| ())?;
+
+        Ok(Self {
+            directory: Mutex::new(directory),
+            debug: options.debug(),
+        })
+    }
+}
+
+impl Transport for FileTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        let serialized = envelope.serialize();
+        let name = event_id(serialized.as_bytes()).unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let result = self
+            .directory
+            .lock()
+            .expect("lock poisoned")
+            .write(&name, serialized.as_bytes());
+
+        match result {
+            Ok(Some(_)) => statistics().record_sent(serialized.as_bytes().len()),
+            Ok(None) => {
+                statistics().record_dropped(DropReason::Other);
+
+                if self.debug {
+                    eprintln!("[sentry-contrib-native] envelope dropped because it's too big");
+                }
+            }
+            Err(error) => {
+                statistics().record_failed();
+                envelope.report_failure();
+
+                if self.debug {
+                    eprintln!(
+                        "[sentry-contrib-native] failed to write envelope to file: {}",
+                        error
+                    );
+                }
+            }
+        }
+    }
+
+    fn shutdown(self: Box<Self>, _: Duration) -> TransportShutdown {
+        // envelopes are written right away
+        TransportShutdown::Success
+    }
+
+    fn flush(&self, _: Duration) -> bool {
+        // envelopes are written right away
+        true
+    }
+}
+
+/// Extracts the event ID from the header of `envelope` if it's usable as a
+/// file name.
+fn event_id(envelope: &[u8]) -> Option<String> {
+    let (header, _) = split(envelope).ok()?;
+
+    header
+        .get("event_id")
+        .and_then(JsonValue::as_str)
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 36
+                && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+        })
+        .map(ToOwned::to_owned)
+}
+
+/// Directory of envelope files, in the order they were written.
+#[derive(Debug)]
+struct Directory {
+    /// Path of the directory.
+    dir: PathBuf,
+    /// Maximum number of files.
+    max_count: usize,
+    /// Maximum size in bytes of all files.
+    max_size: u64,
+    /// Path and size in bytes of every file, oldest first.
+    files: VecDeque<(PathBuf, u64)>,
+    /// Size in bytes of all files.
+    size: u64,
+}
+
+impl Directory {
+    /// Opens the [`Directory`] in `dir`, creating it if it doesn't exist.
+    /// Deletes left over temporary files and the oldest files exceeding the
+    /// limits.
+    fn open(dir: PathBuf, max_count: usize, max_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+
+            // left over from an interrupted write
+            if path.extension().and_then(OsStr::to_str) == Some("tmp") {
+                let _result = fs::remove_file(&path);
+            }
+        }
+
+        let mut directory = Self {
+            dir,
+            max_count,
+            max_size,
+            files: VecDeque::new(),
+            size: 0,
+        };
+        directory.scan()?;
+        directory.rotate(0, 0);
+
+        Ok(directory)
+    }
+
+    /// Reads the files in the directory, ordered by their modification time.
+    fn scan(&mut self) -> io::Result<()> {
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.extension().and_then(OsStr::to_str) == Some("envelope") {
+                if let Ok(metadata) = entry.metadata() {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    files.push((modified, path, metadata.len()));
+                }
+            }
+        }
+
+        files.sort();
+        self.size = files.iter().map(|(_, _, size)| size).sum();
+        self.files = files
+            .into_iter()
+            .map(|(_, path, size)| (path, size))
+            .collect();
+
+        Ok(())
+    }
+
+    /// Forgets files that were removed by other tools.
+    fn refresh(&mut self) {
+        self.files.retain(|(path, _)| path.exists());
+        self.size = self.files.iter().map(|(_, size)| size).sum();
+    }
+
+    /// Deletes the oldest files until `count` more files with `size` bytes
+    /// fit into the limits.
+    fn rotate(&mut self, count: usize, size: u64) {
+        while !self.files.is_empty()
+            && (self.files.len() + count > self.max_count || self.size + size > self.max_size)
+        {
+            if let Some((path, size)) = self.files.pop_front() {
+                self.size -= size;
+                let _result = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Writes `envelope` to a file named after `name`. Returns its path or
+    /// [`None`] if it's bigger than [`Directory::max_size`].
+    fn write(&mut self, name: &str, envelope: &[u8]) -> io::Result<Option<PathBuf>> {
+        let size = u64::try_from(envelope.len()).unwrap_or(u64::MAX);
+
+        if self.max_count == 0 || size > self.max_size {
+            return Ok(None);
+        }
+
+        // only check for removed files if files would have to be deleted
+        if self.files.len() >= self.max_count || self.size + size > self.max_size {
+            self.refresh();
+            self.rotate(1, size);
+        }
+
+        // write to a temporary file first, so no partial envelopes are collected
+        let path = self.path(name);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, envelope)?;
+        fs::rename(&temp, &path)?;
+
+        self.files.push_back((path.clone(), size));
+        self.size += size;
+
+        Ok(Some(path))
+    }
+
+    /// Unused path of a file named after `name`.
+    fn path(&self, name: &str) -> PathBuf {
+        let mut path = self.dir.join(format!("{}.envelope", name));
+        let mut index = 1;
+
+        while path.exists() {
+            path = self.dir.join(format!("{}-{}.envelope", name, index));
+            index += 1;
+        }
+
+        path
+    }
+}
+
+#[test]
+fn directory() -> anyhow::Result<()> {
+    use std::{env, process};
+
+    let dir = env::temp_dir().join(format!("sentry-contrib-native-file-{}", process::id()));
+    let _result = fs::remove_dir_all(&dir);
+
+    let mut directory = Directory::open(dir.clone(), 3, 10)?;
+
+    let first = directory.write("a", b"0000")?.unwrap();
+    assert_eq!(dir.join("a.envelope"), first);
+    assert_eq!(b"0000", fs::read(&first)?.as_slice());
+    // names are unique
+    assert_eq!(
+        Some(dir.join("a-1.envelope")),
+        directory.write("a", b"1111")?
+    );
+    // too big
+    assert_eq!(None, directory.write("b", b"22222222222")?);
+    // deletes the oldest file because of the size limit
+    assert_eq!(Some(dir.join("c.envelope")), directory.write("c", b"333")?);
+    assert!(!first.exists());
+    // files removed by other tools make room
+    fs::remove_file(dir.join("a-1.envelope"))?;
+    directory.write("d", b"4")?;
+    directory.write("e", b"5")?;
+    assert!(dir.join("c.envelope").exists());
+    // deletes the oldest file because of the count limit
+    directory.write("f", b"6")?;
+    assert!(!dir.join("c.envelope").exists());
+
+    fs::write(dir.join("g.tmp"), b"partial")?;
+    fs::write(dir.join("unrelated.txt"), b"unrelated")?;
+
+    // limits are applied to existing files
+    let directory = Directory::open(dir.clone(), 1, 10)?;
+    assert_eq!(1, directory.files.len());
+    assert!(!dir.join("g.tmp").exists());
+    assert!(dir.join("unrelated.txt").exists());
+
+    assert_eq!(
+        Some("9ec79c33-ec99-42ab-8353-589fcb2e04dc".into()),
+        event_id(b"{\"event_id\":\"9ec79c33-ec99-42ab-8353-589fcb2e04dc\"}\n")
+    );
+    assert_eq!(None, event_id(b"{\"event_id\":\"../../etc/passwd\"}\n"));
+    assert_eq!(None, event_id(b"{}\n"));
+
+    fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn file() -> anyhow::Result<()> {
+    use crate::{test, Event};
+    use std::env;
+
+    test::set_hook();
+
+    let dir = env::temp_dir().join("sentry-contrib-native-file-transport");
+    let _result = fs::remove_dir_all(&dir);
+
+    let mut options = Options::new();
+    options.set_transport({
+        let dir = dir.clone();
+        move |options| FileTransport::new(dir, options)
+    });
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    assert_eq!(3, fs::read_dir(&dir)?.count());
+
+    test::verify_panics();
+
+    Ok(())
+}
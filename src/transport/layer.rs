@@ -0,0 +1,285 @@
+//! Composable layers wrapping a [`Transport`].
+
+use crate::{RawEnvelope, Transport, TransportShutdown};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    time::{Duration, Instant},
+};
+
+/// Middleware wrapping a [`Transport`], see [`TransportBuilder`].
+///
+/// A layer can inspect or transform envelopes, e.g. with
+/// [`RawEnvelope::serialize`] and
+/// [`RawEnvelope::from`](RawEnvelope#impl-From<Envelope>), before passing them
+/// on to the next [`Transport`], or drop them by not passing them on.
+///
+/// Functions taking a [`RawEnvelope`] and the next [`Transport`] implement
+/// [`Layer`].
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::{Layer, RawEnvelope, Transport};
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// /// Only passes on the first `limit` envelopes.
+/// struct Limit {
+///     limit: usize,
+///     sent: AtomicUsize,
+/// }
+///
+/// impl Layer for Limit {
+///     fn send(&self, envelope: RawEnvelope, next: &dyn Transport) {
+///         if self.sent.fetch_add(1, Ordering::SeqCst) < self.limit {
+///             next.send(envelope);
+///         }
+///     }
+/// }
+/// ```
+pub trait Layer: 'static + Send + Sync {
+    /// Handles `envelope`, which has to be passed on to `next` to be sent.
+    fn send(&self, envelope: RawEnvelope, next: &dyn Transport);
+
+    /// Shuts down this layer, e.g. passing on buffered envelopes to `next`,
+    /// before `next` is shut down with the remaining time of `timeout`.
+    ///
+    /// The default implementation does nothing and returns
+    /// [`TransportShutdown::Success`].
+    #[must_use]
+    #[allow(clippy::boxed_local, unused_variables)]
+    fn shutdown(self: Box<Self>, next: &dyn Transport, timeout: Duration) -> TransportShutdown {
+        TransportShutdown::Success
+    }
+
+    /// Flushes this layer, e.g. passing on buffered envelopes to `next`,
+    /// before `next` is flushed with the remaining time of `timeout`. Returns
+    /// `false` if it timed out.
+    ///
+    /// The default implementation does nothing and returns `true`.
+    #[must_use]
+    #[allow(unused_variables)]
+    fn flush(&self, next: &dyn Transport, timeout: Duration) -> bool {
+        true
+    }
+}
+
+impl<T: Fn(RawEnvelope, &dyn Transport) + 'static + Send + Sync> Layer for T {
+    fn send(&self, envelope: RawEnvelope, next: &dyn Transport) {
+        self(envelope, next);
+    }
+}
+
+/// Builds a [`LayeredTransport`] by wrapping a [`Transport`] in [`Layer`]s.
+///
+/// Every layer wraps everything added before it, so envelopes pass through the
+/// layers in the reverse order they were added, the last layer sees them
+/// first. On shutdown, layers are shut down in the same order, each one
+/// before the [`Transport`] it wraps, which gets the time left over. Flushing
+/// works the same way.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{Event, Options, RawEnvelope, Transport, TransportBuilder};
+/// let mut options = Options::new();
+/// options.set_transport(|_| {
+///     Ok(TransportBuilder::new(|envelope: RawEnvelope| {
+///         // send the envelope
+///     })
+///     .layer(|envelope: RawEnvelope, next: &dyn Transport| {
+///         // drop envelopes without an event
+///         if !envelope.event().is_null() {
+///             next.send(envelope);
+///         }
+///     })
+///     .build())
+/// });
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+pub struct TransportBuilder {
+    /// The [`Transport`] with all [`Layer`]s added so far.
+    transport: Box<dyn Transport>,
+}
+
+impl Debug for TransportBuilder {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("TransportBuilder").finish_non_exhaustive()
+    }
+}
+
+impl TransportBuilder {
+    /// Creates a new [`TransportBuilder`] wrapping `transport`.
+    pub fn new<T: Transport>(transport: T) -> Self {
+        Self {
+            transport: Box::new(transport),
+        }
+    }
+
+    /// Wraps the [`Transport`] and all previously added [`Layer`]s in
+    /// `layer`.
+    #[must_use]
+    pub fn layer<L: Layer>(self, layer: L) -> Self {
+        Self {
+            transport: Box::new(Layered {
+                layer: Box::new(layer),
+                next: self.transport,
+            }),
+        }
+    }
+
+    /// Builds the [`LayeredTransport`].
+    #[must_use]
+    pub fn build(self) -> LayeredTransport {
+        LayeredTransport {
+            transport: self.transport,
+        }
+    }
+}
+
+/// [`Transport`] wrapped in [`Layer`]s, see [`TransportBuilder`].
+pub struct LayeredTransport {
+    /// The outermost [`Layer`].
+    transport: Box<dyn Transport>,
+}
+
+impl Debug for LayeredTransport {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("LayeredTransport").finish_non_exhaustive()
+    }
+}
+
+impl Transport for LayeredTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        self.transport.send(envelope);
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        self.transport.shutdown(timeout)
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        self.transport.flush(timeout)
+    }
+}
+
+/// A single [`Layer`] wrapping the next [`Transport`].
+struct Layered {
+    /// The [`Layer`].
+    layer: Box<dyn Layer>,
+    /// The wrapped [`Transport`].
+    next: Box<dyn Transport>,
+}
+
+impl Transport for Layered {
+    fn send(&self, envelope: RawEnvelope) {
+        self.layer.send(envelope, self.next.as_ref());
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        let Self { layer, next } = *self;
+        let start = Instant::now();
+        let layer = layer.shutdown(next.as_ref(), timeout);
+        // give the wrapped transport the time left over
+        let next = next.shutdown(timeout.saturating_sub(start.elapsed()));
+
+        if layer == TransportShutdown::Success && next == TransportShutdown::Success {
+            TransportShutdown::Success
+        } else {
+            TransportShutdown::TimedOut
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        let layer = self.layer.flush(self.next.as_ref(), timeout);
+        // give the wrapped transport the time left over
+        let next = self.next.flush(timeout.saturating_sub(start.elapsed()));
+
+        layer && next
+    }
+}
+
+#[test]
+fn layers() {
+    use crate::Envelope;
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Records envelopes and the timeout it was shut down with.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        /// Received envelopes.
+        envelopes: Arc<Mutex<Vec<Vec<u8>>>>,
+        /// Timeout passed to [`Transport::shutdown`].
+        timeout: Arc<Mutex<Option<Duration>>>,
+    }
+
+    impl Transport for Recorder {
+        fn send(&self, envelope: RawEnvelope) {
+            self.envelopes
+                .lock()
+                .unwrap()
+                .push(envelope.serialize().as_bytes().to_vec());
+        }
+
+        fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+            *self.timeout.lock().unwrap() = Some(timeout);
+            TransportShutdown::Success
+        }
+    }
+
+    /// Appends a byte to every envelope, sleeps on shutdown.
+    struct Append(u8);
+
+    impl Layer for Append {
+        fn send(&self, envelope: RawEnvelope, next: &dyn Transport) {
+            let mut bytes = envelope.serialize().as_bytes().to_vec();
+            bytes.push(self.0);
+            next.send(Envelope::from(bytes).into());
+        }
+
+        fn shutdown(self: Box<Self>, _: &dyn Transport, _: Duration) -> TransportShutdown {
+            thread::sleep(Duration::from_millis(50));
+            TransportShutdown::Success
+        }
+    }
+
+    let recorder = Recorder::default();
+    let transport = TransportBuilder::new(recorder.clone())
+        .layer(Append(b'a'))
+        .layer(|envelope: RawEnvelope, next: &dyn Transport| {
+            // drop envelopes without a header
+            if envelope.serialize().as_bytes().starts_with(b"{") {
+                next.send(envelope);
+            }
+        })
+        .layer(Append(b'b'))
+        .build();
+
+    transport.send(Envelope::from(b"{}".to_vec()).into());
+    transport.send(Envelope::from(Vec::new()).into());
+
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_secs(1))
+    );
+
+    // the last layer sees envelopes first
+    assert_eq!(vec![b"{}ba".to_vec()], *recorder.envelopes.lock().unwrap());
+    // both `Append` layers used up some of the timeout
+    assert!(recorder.timeout.lock().unwrap().unwrap() <= Duration::from_millis(900));
+
+    // the next transport isn't given any time if a layer used it all
+    let recorder = Recorder::default();
+    let transport = TransportBuilder::new(recorder.clone())
+        .layer(Append(b'a'))
+        .build();
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_millis(10))
+    );
+    assert_eq!(Some(Duration::ZERO), *recorder.timeout.lock().unwrap());
+}
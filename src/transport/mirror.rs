@@ -0,0 +1,356 @@
+//! Transport sending every envelope to multiple destinations.
+
+use super::envelope::set_header;
+use crate::{Dsn, Envelope, RawEnvelope, Transport, TransportShutdown};
+use ::{
+    serde_json::Value as JsonValue,
+    std::{
+        fmt::{Debug, Formatter, Result as FmtResult},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread::Builder,
+        time::Duration,
+    },
+};
+
+/// [`Transport`] sending every envelope to multiple destinations, e.g. to
+/// deliver all events to two projects while migrating between them.
+///
+/// Every destination gets its own copy of the envelope, with the `dsn` in the
+/// envelope header replaced by the [`Dsn`] of that destination. The
+/// [`Transport`] of a destination has to be configured to send to that
+/// [`Dsn`] too. Failures of one destination don't affect the others, only if
+/// all of them failed the envelope is reported as failed, see
+/// [`RawEnvelope::on_delivery`]. Without any destinations every envelope is
+/// reported as failed.
+///
+/// [`Transport::flush`] and [`Transport::shutdown`] are passed on to all
+/// destinations at the same time, each with the full timeout.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # #[cfg(feature = "transport-rustls")]
+/// # {
+/// # use sentry_contrib_native::{Dsn, Event, MirrorTransport, Options, RustlsTransport};
+/// let mut options = Options::new();
+/// options.set_dsn("https://public_key_1234@organization_1234.ingest.sentry.io/project_id_1234");
+/// options.set_transport(|options| {
+///     let dsn = options.dsn().and_then(|dsn| Dsn::new(dsn).ok()).ok_or(())?;
+///
+///     let mut self_hosted = Options::new();
+///     self_hosted.set_dsn("https://public_key_5678@sentry.example.com/project_id_5678");
+///     let self_hosted_dsn = Dsn::new(self_hosted.dsn().ok_or(())?).map_err(|_| ())?;
+///
+///     Ok(MirrorTransport::new()
+///         .destination(dsn, RustlsTransport::new(options)?)
+///         .destination(self_hosted_dsn, RustlsTransport::new(&self_hosted)?))
+/// });
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # }
+/// # Ok(()) }
+/// ```
+#[derive(Default)]
+pub struct MirrorTransport {
+    /// Destinations every envelope is sent to.
+    destinations: Vec<Destination>,
+}
+
+impl Debug for MirrorTransport {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("MirrorTransport")
+            .field("destinations", &self.destinations)
+            .finish()
+    }
+}
+
+/// A single destination of a [`MirrorTransport`] or
+/// [`RoutingTransport`](crate::RoutingTransport).
+pub(super) struct Destination {
+    /// [`Dsn`] written into the envelope header.
+    pub(super) dsn: Dsn,
+    /// [`Transport`] sending to `dsn`, shared with the threads flushing it.
+    pub(super) transport: Arc<Box<dyn Transport>>,
+}
+
+impl Debug for Destination {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("Destination")
+            .field("dsn", &self.dsn)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Destination {
+    /// Creates a new [`Destination`] sending to `dsn` with `transport`.
+    pub(super) fn new<T: Transport>(dsn: Dsn, transport: T) -> Self {
+        Self {
+            dsn,
+            transport: Arc::new(Box::new(transport)),
+        }
+    }
+
+    /// Copies `envelope` with the `dsn` in its header replaced, to be sent
+    /// with this destination's [`Transport`].
+    pub(super) fn prepare(&self, envelope: &[u8]) -> RawEnvelope {
+        // envelopes with a malformed header are passed on unchanged
+        let bytes = set_header(envelope, "dsn", JsonValue::String(self.dsn.to_string()))
+            .unwrap_or_else(|_| envelope.to_vec());
+
+        RawEnvelope::from(Envelope::from(bytes))
+    }
+}
+
+impl MirrorTransport {
+    /// Creates a new [`MirrorTransport`] without any destinations.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a destination, sending every envelope with `transport`, which
+    /// has to be configured to send to `dsn`.
+    #[must_use]
+    pub fn destination<T: Transport>(mut self, dsn: Dsn, transport: T) -> Self {
+        self.destinations.push(Destination::new(dsn, transport));
+
+        self
+    }
+}
+
+impl Transport for MirrorTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        if self.destinations.is_empty() {
+            envelope.report_failure();
+            return;
+        }
+
+        let mirrored = Arc::new(Mirrored {
+            envelope: envelope.serialize(),
+            failures: AtomicUsize::new(0),
+            destinations: self.destinations.len(),
+        });
+
+        for destination in &self.destinations {
+            let mut copy = destination.prepare(mirrored.envelope.as_bytes());
+            let mirrored = Arc::clone(&mirrored);
+            copy.on_delivery(move |delivered| {
+                if !delivered {
+                    mirrored.failed();
+                }
+            });
+
+            destination.transport.send(copy);
+        }
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        shutdown_all(self.destinations, timeout)
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        flush_all(&self.destinations, timeout)
+    }
+}
+
+/// Envelope passed to [`MirrorTransport`], which is delivered once all copies
+/// were delivered or failed.
+struct Mirrored {
+    /// The envelope, its failure is reported if all copies failed.
+    envelope: Envelope,
+    /// Number of copies that failed so far.
+    failures: AtomicUsize,
+    /// Number of copies.
+    destinations: usize,
+}
+
+impl Mirrored {
+    /// Records the failure of a copy.
+    fn failed(&self) {
+        if self.failures.fetch_add(1, Ordering::SeqCst) + 1 == self.destinations {
+            self.envelope.report_failure();
+        }
+    }
+}
+
+/// Shuts down the [`Transport`]s of all `destinations` at the same time, each
+/// with the full `timeout`. Flushes still running are waited for first, so
+/// every [`Transport`] is shut down.
+pub(super) fn shutdown_all<I: IntoIterator<Item = Destination>>(
+    destinations: I,
+    timeout: Duration,
+) -> TransportShutdown {
+    let transports = destinations
+        .into_iter()
+        .map(|destination| destination.transport)
+        .collect();
+
+    let results = parallel(transports, move |transport| {
+        super::into_owned(transport).shutdown(timeout)
+    });
+
+    if results
+        .into_iter()
+        .all(|result| result == Some(TransportShutdown::Success))
+    {
+        TransportShutdown::Success
+    } else {
+        TransportShutdown::TimedOut
+    }
+}
+
+/// Flushes the [`Transport`]s of all `destinations` at the same time, each
+/// with the full `timeout`.
+pub(super) fn flush_all<'a, I: IntoIterator<Item = &'a Destination>>(
+    destinations: I,
+    timeout: Duration,
+) -> bool {
+    let transports = destinations
+        .into_iter()
+        .map(|destination| Arc::clone(&destination.transport))
+        .collect();
+
+    parallel(transports, move |transport| transport.flush(timeout))
+        .into_iter()
+        .all(|flushed| flushed == Some(true))
+}
+
+/// Calls `f` with every [`Transport`], each on its own thread, and collects
+/// the results. Results are [`None`] if the thread couldn't be spawned or
+/// `f` panicked.
+fn parallel<R, F>(transports: Vec<Arc<Box<dyn Transport>>>, f: F) -> Vec<Option<R>>
+where
+    R: 'static + Send,
+    F: Fn(Arc<Box<dyn Transport>>) -> R + 'static + Send + Sync,
+{
+    let f = Arc::new(f);
+
+    let handles: Vec<_> = transports
+        .into_iter()
+        .map(|transport| {
+            let f = Arc::clone(&f);
+
+            Builder::new()
+                .name("sentry-mirror".into())
+                .spawn(move || f(transport))
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .map(|handle| handle.ok()?.join().ok())
+        .collect()
+}
+
+#[test]
+fn mirror() -> anyhow::Result<()> {
+    use std::sync::Mutex;
+
+    /// Records envelopes, optionally reporting them as failed.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        /// Received envelopes.
+        envelopes: Arc<Mutex<Vec<Vec<u8>>>>,
+        /// Report every envelope as failed.
+        fail: bool,
+    }
+
+    impl Transport for Recorder {
+        fn send(&self, envelope: RawEnvelope) {
+            if self.fail {
+                envelope.report_failure();
+            }
+
+            self.envelopes
+                .lock()
+                .unwrap()
+                .push(envelope.serialize().as_bytes().to_vec());
+        }
+
+        fn shutdown(self: Box<Self>, _: Duration) -> TransportShutdown {
+            TransportShutdown::Success
+        }
+
+        fn flush(&self, _: Duration) -> bool {
+            true
+        }
+    }
+
+    /// Sends an envelope through `transport`, returns if it was delivered.
+    fn send(transport: &MirrorTransport) -> bool {
+        let delivered = Arc::new(Mutex::new(None));
+        let mut envelope = RawEnvelope::from(Envelope::from(
+            b"{\"dsn\":\"https://old@sentry.io/1\"}\n{\"type\":\"event\",\"length\":2}\n{}\n"
+                .to_vec(),
+        ));
+        envelope.on_delivery({
+            let delivered = Arc::clone(&delivered);
+            move |result| *delivered.lock().unwrap() = Some(result)
+        });
+        transport.send(envelope);
+
+        let delivered = *delivered.lock().unwrap();
+        delivered.expect("envelope not delivered")
+    }
+
+    let first = Recorder::default();
+    let second = Recorder::default();
+    let transport = MirrorTransport::new()
+        .destination(Dsn::new("https://first@sentry.io/1")?, first.clone())
+        .destination(
+            Dsn::new("https://second@sentry.example.com/2")?,
+            second.clone(),
+        );
+
+    assert!(send(&transport));
+    assert_eq!(
+        b"{\"dsn\":\"https://first@sentry.io/1\"}\n{\"type\":\"event\",\"length\":2}\n{}\n",
+        first.envelopes.lock().unwrap()[0].as_slice()
+    );
+    assert_eq!(
+        b"{\"dsn\":\"https://second@sentry.example.com/2\"}\n{\"type\":\"event\",\"length\":2}\n{}\n",
+        second.envelopes.lock().unwrap()[0].as_slice()
+    );
+    assert!(transport.flush(Duration::from_secs(1)));
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_secs(1))
+    );
+
+    // only failed if all destinations failed
+    let failing = Recorder {
+        fail: true,
+        ..Recorder::default()
+    };
+    let transport = MirrorTransport::new()
+        .destination(Dsn::new("https://first@sentry.io/1")?, failing.clone())
+        .destination(Dsn::new("https://second@sentry.io/2")?, Recorder::default());
+    assert!(send(&transport));
+
+    let transport = MirrorTransport::new()
+        .destination(Dsn::new("https://first@sentry.io/1")?, failing.clone())
+        .destination(Dsn::new("https://second@sentry.io/2")?, failing);
+    assert!(!send(&transport));
+
+    // nothing is delivered without destinations
+    assert!(!send(&MirrorTransport::new()));
+
+    // waits for flushes still running before shutting down
+    let destination = Destination::new(Dsn::new("https://first@sentry.io/1")?, first);
+    let flushing = Arc::clone(&destination.transport);
+    let flush = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        drop(flushing);
+    });
+    assert_eq!(
+        TransportShutdown::Success,
+        shutdown_all(vec![destination], Duration::from_secs(1))
+    );
+    flush.join().unwrap();
+
+    Ok(())
+}
@@ -0,0 +1,424 @@
+//! Rate limiting as requested by the Sentry service.
+
+use super::envelope::{join, split};
+use crate::Envelope;
+use ::{
+    http::HeaderMap,
+    serde_json::{Map as JsonMap, Value as JsonValue},
+    std::time::{Duration, Instant},
+};
+
+/// Data category a rate limit applies to.
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Category {
+    /// Events.
+    Error,
+    /// Sessions.
+    Session,
+    /// Attachments, including minidumps.
+    Attachment,
+    /// Transactions.
+    Transaction,
+}
+
+impl Category {
+    /// Determines the [`Category`] of an envelope item from its `type` header.
+    /// Returns [`None`] for types that aren't rate limited by category.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::Category;
+    /// assert_eq!(Some(Category::Error), Category::from_item_type("event"));
+    /// assert_eq!(None, Category::from_item_type("client_report"));
+    /// ```
+    #[must_use]
+    pub fn from_item_type(item_type: &str) -> Option<Self> {
+        match item_type {
+            "event" => Some(Self::Error),
+            "session" | "sessions" => Some(Self::Session),
+            "attachment" => Some(Self::Attachment),
+            "transaction" => Some(Self::Transaction),
+            _ => None,
+        }
+    }
+
+    /// Determines the [`Category`] of a data category in the
+    /// `X-Sentry-Rate-Limits` header.
+    fn from_limit(category: &str) -> Option<Self> {
+        match category {
+            "default" | "error" => Some(Self::Error),
+            "session" => Some(Self::Session),
+            "attachment" => Some(Self::Attachment),
+            "transaction" => Some(Self::Transaction),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps track of the rate limits received from the Sentry service and decides
+/// which envelopes should be dropped instead of sent.
+///
+/// Used by the shipped transports, custom transports can plug it in by
+/// updating it with every response and filtering envelopes before sending
+/// them, see [`RateLimiter::filter`].
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::{Category, RateLimiter};
+/// let mut limiter = RateLimiter::new();
+/// limiter.update(200, Some("60:transaction:organization"), None);
+///
+/// assert!(limiter.is_limited(Category::Transaction));
+/// assert!(!limiter.is_limited(Category::Error));
+///
+/// // `429 Too Many Requests` without any headers limits everything
+/// limiter.update(429, None, None);
+/// assert!(limiter.is_limited(Category::Error));
+/// ```
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct RateLimiter {
+    /// Limit applying to all categories.
+    all: Option<Instant>,
+    /// Limit for [`Category::Error`].
+    error: Option<Instant>,
+    /// Limit for [`Category::Session`].
+    session: Option<Instant>,
+    /// Limit for [`Category::Attachment`].
+    attachment: Option<Instant>,
+    /// Limit for [`Category::Transaction`].
+    transaction: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Duration to wait if the Sentry service didn't specify any.
+    pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+    /// Longest duration a limit is honored for, longer durations received
+    /// from the Sentry service are clamped to it.
+    pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Creates a new [`RateLimiter`] without any active limits.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the limits from the status code and headers of a response.
+    ///
+    /// `rate_limits` is the value of the `X-Sentry-Rate-Limits` header, which
+    /// takes precedence, and `retry_after` the value of the `Retry-After`
+    /// header, which is only considered for `429 Too Many Requests`.
+    pub fn update(&mut self, status: u16, rate_limits: Option<&str>, retry_after: Option<&str>) {
+        if let Some(rate_limits) = rate_limits {
+            self.update_from_rate_limits(rate_limits);
+        } else if status == 429 {
+            self.update_from_retry_after(retry_after.unwrap_or_default());
+        }
+    }
+
+    /// Updates the limits from the status code and headers of a response, see
+    /// [`RateLimiter::update`].
+    pub fn update_from_headers(&mut self, status: u16, headers: &HeaderMap) {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        self.update(
+            status,
+            header("x-sentry-rate-limits"),
+            header("retry-after"),
+        );
+    }
+
+    /// Updates the limits from the value of a `X-Sentry-Rate-Limits` header.
+    /// Limits without any known category are ignored.
+    pub fn update_from_rate_limits(&mut self, rate_limits: &str) {
+        let now = Instant::now();
+
+        for limit in rate_limits.split(',') {
+            let mut parts = limit.trim().split(':');
+
+            let until = Self::until(now, parts.next().unwrap_or_default());
+            let categories = parts.next().unwrap_or_default().trim();
+
+            if categories.is_empty() {
+                Self::extend(&mut self.all, until);
+            } else {
+                for category in categories.split(';').filter_map(Category::from_limit) {
+                    Self::extend(self.limit_mut(category), until);
+                }
+            }
+        }
+    }
+
+    /// Updates the limits from the value of a `Retry-After` header, which
+    /// applies to all categories. Falls back to
+    /// [`RateLimiter::DEFAULT_RETRY_AFTER`] if the value isn't a number of
+    /// seconds.
+    pub fn update_from_retry_after(&mut self, retry_after: &str) {
+        Self::extend(&mut self.all, Self::until(Instant::now(), retry_after));
+    }
+
+    /// Parses `secs` as the number of seconds to wait, starting at `now`,
+    /// clamped to [`RateLimiter::MAX_RETRY_AFTER`]. Falls back to
+    /// [`RateLimiter::DEFAULT_RETRY_AFTER`] if `secs` isn't a number of
+    /// seconds.
+    fn until(now: Instant, secs: &str) -> Instant {
+        let retry_after = match secs.trim().parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs >= 0. => {
+                Duration::from_secs_f64(secs.min(Self::MAX_RETRY_AFTER.as_secs_f64()))
+            }
+            _ => Self::DEFAULT_RETRY_AFTER,
+        };
+
+        now.checked_add(retry_after)
+            .or_else(|| now.checked_add(Self::MAX_RETRY_AFTER))
+            .unwrap_or(now)
+    }
+
+    /// Returns how long `category` is still limited for, if at all.
+    #[must_use]
+    pub fn retry_after(&self, category: Category) -> Option<Duration> {
+        let now = Instant::now();
+
+        [self.all, self.limit(category)]
+            .iter()
+            .flatten()
+            .filter(|until| **until > now)
+            .max()
+            .map(|until| *until - now)
+    }
+
+    /// Returns `true` if `category` is currently limited.
+    #[must_use]
+    pub fn is_limited(&self, category: Category) -> bool {
+        self.retry_after(category).is_some()
+    }
+
+    /// Returns `true` if an envelope item with the given `type` header should
+    /// be dropped right now.
+    #[must_use]
+    pub fn is_item_limited(&self, item_type: &str) -> bool {
+        match Category::from_item_type(item_type) {
+            Some(category) => self.is_limited(category),
+            None => self.is_all_limited(),
+        }
+    }
+
+    /// Returns `true` if all categories are currently limited.
+    fn is_all_limited(&self) -> bool {
+        matches!(self.all, Some(until) if until > Instant::now())
+    }
+
+    /// Returns `true` if `envelope` should be dropped right now, which is the
+    /// case if all of its items are limited. Envelopes whose items can't be
+    /// determined are only dropped if all categories are limited.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{RateLimiter, RawEnvelope, Transport};
+    /// struct CustomTransport {
+    ///     limiter: RateLimiter,
+    /// };
+    ///
+    /// impl Transport for CustomTransport {
+    ///     fn send(&self, raw_envelope: RawEnvelope) {
+    ///         let envelope = raw_envelope.serialize();
+    ///
+    ///         if self.limiter.should_drop(&envelope) {
+    ///             return;
+    ///         }
+    ///
+    ///         // send `envelope` and pass the response to `RateLimiter::update`
+    ///     }
+    /// }
+    /// ```
+    #[must_use]
+    pub fn should_drop(&self, envelope: &Envelope) -> bool {
+        // not using `Envelope::parse` to avoid copying payloads
+        match split(envelope.as_bytes()) {
+            Ok((_, items)) if !items.is_empty() => items
+                .iter()
+                .all(|(header, _)| self.is_item_limited(item_type(header))),
+            _ => self.is_all_limited(),
+        }
+    }
+
+    /// Removes all items from `envelope` that are currently limited. Returns
+    /// [`None`] if no item is left, in which case the envelope should be
+    /// dropped. Envelopes whose items can't be determined are only dropped if
+    /// all categories are limited.
+    ///
+    /// # Examples
+    /// ```
+    /// # use sentry_contrib_native::{RateLimiter, RawEnvelope, Transport};
+    /// struct CustomTransport {
+    ///     limiter: RateLimiter,
+    /// };
+    ///
+    /// impl Transport for CustomTransport {
+    ///     fn send(&self, raw_envelope: RawEnvelope) {
+    ///         let envelope = match self.limiter.filter(raw_envelope.serialize()) {
+    ///             Some(envelope) => envelope,
+    ///             None => return,
+    ///         };
+    ///
+    ///         // send `envelope` and pass the response to `RateLimiter::update`
+    ///     }
+    /// }
+    /// ```
+    #[must_use]
+    pub fn filter(&self, envelope: Envelope) -> Option<Envelope> {
+        // `None` drops the envelope, `Some(None)` keeps it unchanged
+        let filtered = match split(envelope.as_bytes()) {
+            Ok((header, items)) if !items.is_empty() => {
+                let count = items.len();
+                let items: Vec<_> = items
+                    .into_iter()
+                    .filter(|(header, _)| !self.is_item_limited(item_type(header)))
+                    .collect();
+
+                if items.is_empty() {
+                    None
+                } else if items.len() == count {
+                    Some(None)
+                } else {
+                    Some(Some(join(&header, &items)))
+                }
+            }
+            _ if self.is_all_limited() => None,
+            _ => Some(None),
+        };
+
+        filtered.map(|bytes| match bytes {
+            Some(bytes) => envelope.with_bytes(bytes),
+            None => envelope,
+        })
+    }
+
+    /// Returns the limit for `category`.
+    const fn limit(&self, category: Category) -> Option<Instant> {
+        match category {
+            Category::Error => self.error,
+            Category::Session => self.session,
+            Category::Attachment => self.attachment,
+            Category::Transaction => self.transaction,
+        }
+    }
+
+    /// Returns a mutable reference to the limit for `category`.
+    fn limit_mut(&mut self, category: Category) -> &mut Option<Instant> {
+        match category {
+            Category::Error => &mut self.error,
+            Category::Session => &mut self.session,
+            Category::Attachment => &mut self.attachment,
+            Category::Transaction => &mut self.transaction,
+        }
+    }
+
+    /// Extends `limit` to `until`, limits are never shortened.
+    fn extend(limit: &mut Option<Instant>, until: Instant) {
+        if !matches!(limit, Some(limit) if *limit >= until) {
+            *limit = Some(until);
+        }
+    }
+}
+
+/// Yields the `type` of an item header.
+fn item_type(header: &JsonMap<String, JsonValue>) -> &str {
+    header
+        .get("type")
+        .and_then(JsonValue::as_str)
+        .unwrap_or_default()
+}
+
+#[test]
+fn rate_limits() {
+    let mut limiter = RateLimiter::new();
+    assert!(!limiter.is_limited(Category::Error));
+    assert!(!limiter.is_item_limited("client_report"));
+
+    limiter.update(
+        200,
+        Some("60:error;attachment:organization, 2700:session:organization:quota_exceeded"),
+        None,
+    );
+    assert!(limiter.is_limited(Category::Error));
+    assert!(limiter.is_limited(Category::Attachment));
+    assert!(limiter.is_limited(Category::Session));
+    assert!(!limiter.is_limited(Category::Transaction));
+    assert!(!limiter.is_item_limited("client_report"));
+    assert!(limiter.retry_after(Category::Session).unwrap() > Duration::from_secs(2000));
+    assert!(limiter.retry_after(Category::Error).unwrap() <= Duration::from_secs(60));
+
+    // `Retry-After` is ignored if `X-Sentry-Rate-Limits` is present
+    let mut limiter = RateLimiter::new();
+    limiter.update(429, Some("0::organization"), Some("60"));
+    assert!(!limiter.is_limited(Category::Error));
+
+    let mut limiter = RateLimiter::new();
+    limiter.update(429, None, Some("1.5"));
+    assert!(limiter.is_limited(Category::Transaction));
+    assert!(limiter.is_item_limited("client_report"));
+    assert!(limiter.retry_after(Category::Error).unwrap() <= Duration::from_millis(1500));
+
+    let mut limiter = RateLimiter::new();
+    limiter.update(500, None, Some("60"));
+    assert!(!limiter.is_limited(Category::Error));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-sentry-rate-limits",
+        "60:transaction:key".parse().unwrap(),
+    );
+    let mut limiter = RateLimiter::new();
+    limiter.update_from_headers(200, &headers);
+    assert!(limiter.is_limited(Category::Transaction));
+    assert!(!limiter.is_limited(Category::Error));
+
+    // huge values are clamped instead of overflowing
+    let mut limiter = RateLimiter::new();
+    limiter.update(429, None, Some("1e30"));
+    assert!(limiter.retry_after(Category::Error).unwrap() <= RateLimiter::MAX_RETRY_AFTER);
+
+    let mut limiter = RateLimiter::new();
+    limiter.update(
+        200,
+        Some("1e30:error:organization, 18446744073709551615:session:organization"),
+        None,
+    );
+    assert!(limiter.retry_after(Category::Error).unwrap() <= RateLimiter::MAX_RETRY_AFTER);
+    assert!(limiter.retry_after(Category::Session).unwrap() <= RateLimiter::MAX_RETRY_AFTER);
+    assert!(!limiter.is_limited(Category::Transaction));
+}
+
+#[test]
+fn filter() {
+    let envelope = || {
+        Envelope::from(
+            b"{\"event_id\":\"1\"}\n\
+            {\"type\":\"event\",\"length\":2}\n{}\n\
+            {\"type\":\"attachment\"}\nabc\n"
+                .to_vec(),
+        )
+    };
+
+    let limiter = RateLimiter::new();
+    assert_eq!(envelope(), limiter.filter(envelope()).unwrap());
+
+    let mut limiter = RateLimiter::new();
+    limiter.update(429, Some("60:attachment:organization"), None);
+    assert!(!limiter.should_drop(&envelope()));
+    assert_eq!(
+        b"{\"event_id\":\"1\"}\n{\"length\":2,\"type\":\"event\"}\n{}\n".as_ref(),
+        limiter.filter(envelope()).unwrap().as_bytes()
+    );
+
+    limiter.update(429, Some("60:error:organization"), None);
+    assert!(limiter.should_drop(&envelope()));
+    assert_eq!(None, limiter.filter(envelope()));
+
+    // malformed envelopes are only dropped if everything is limited
+    let malformed = || Envelope::from(b"malformed".to_vec());
+    assert!(limiter.filter(malformed()).is_some());
+    limiter.update(429, None, None);
+    assert_eq!(None, limiter.filter(malformed()));
+}
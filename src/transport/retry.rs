@@ -0,0 +1,718 @@
+//! Transport wrapper retrying failed deliveries.
+
+use crate::{statistics, DropReason, Envelope, Options, RawEnvelope, Transport, TransportShutdown};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    hash::{BuildHasher, Hasher},
+    io, mem,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Configures the retries of a [`RetryTransport`].
+///
+/// The delay before the `n`th retry is `initial_backoff * 2^(n - 1)`, at most
+/// `max_backoff`, reduced by a random amount of up to `jitter` times the
+/// delay, so clients that failed at the same time don't retry at the same
+/// time.
+///
+/// # Examples
+/// ```
+/// # use sentry_contrib_native::RetryPolicy;
+/// # use std::time::Duration;
+/// let policy = RetryPolicy {
+///     max_retries: 3,
+///     initial_backoff: Duration::from_millis(500),
+///     ..RetryPolicy::default()
+/// };
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of a single envelope.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Maximum delay between retries.
+    pub max_backoff: Duration,
+    /// Fraction of the delay that is randomized, between `0` and `1`.
+    pub jitter: f64,
+    /// Number of consecutive failures after which the circuit opens.
+    pub failure_threshold: u32,
+    /// Time the circuit stays open before a single envelope is let through to
+    /// probe if the service recovered.
+    pub open_duration: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            jitter: 0.5,
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry `attempt`, starting at `1`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+
+        backoff.mul_f64(1. - self.jitter.clamp(0., 1.) * random())
+    }
+}
+
+/// Random number between `0` and `1`.
+#[allow(clippy::cast_precision_loss)]
+fn random() -> f64 {
+    // every `RandomState` is seeded with different random keys
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// [`Transport`] wrapping another [`Transport`], which retries envelopes whose
+/// delivery failed, see [`RetryPolicy`].
+///
+/// Envelopes are retried if the wrapped [`Transport`] reported a failure with
+/// [`RawEnvelope::report_failure`]. The shipped transports do that for
+/// connection errors and `5xx` responses, but not for `4xx` responses, which
+/// won't succeed when retried, or envelopes dropped because of rate limits.
+/// Retried envelopes can be sent out of order.
+///
+/// After [`RetryPolicy::failure_threshold`] consecutive failures the circuit
+/// opens: new envelopes are dropped and reported as failed, e.g. to be kept by
+/// a [`DiskQueue`](crate::DiskQueue) wrapping this transport, and retries are
+/// postponed. After [`RetryPolicy::open_duration`] a single envelope is let
+/// through, if it's delivered the circuit closes again, otherwise it stays
+/// open.
+///
+/// [`Transport::shutdown`] keeps retrying until all envelopes were delivered
+/// or the timeout is reached, envelopes that would be retried later are given
+/// up and reported as failed. The wrapped [`Transport`] is then shut down with
+/// the remaining time.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{Event, Options, RawEnvelope, RetryTransport};
+/// let mut options = Options::new();
+/// options.set_transport(|options| {
+///     RetryTransport::new(
+///         |envelope: RawEnvelope| {
+///             // send the envelope, report failures with `RawEnvelope::report_failure`
+///         },
+///         options,
+///     )
+/// });
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+pub struct RetryTransport {
+    /// The wrapped [`Transport`], shared with the retry thread.
+    transport: Arc<Box<dyn Transport>>,
+    /// State shared with the retry thread and delivery callbacks.
+    shared: Arc<Shared>,
+    /// Thread sending retries when they are due.
+    thread: JoinHandle<()>,
+}
+
+impl Debug for RetryTransport {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("RetryTransport")
+            .field("shared", &self.shared)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryTransport {
+    /// Creates a new [`RetryTransport`] wrapping `transport` with the default
+    /// [`RetryPolicy`].
+    ///
+    /// If [`Options::debug`] is enabled, envelopes that are given up are
+    /// printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if the retry thread couldn't be spawned.
+    #[allow(clippy::result_unit_err)]
+    pub fn new<T: Transport>(transport: T, options: &Options) -> Result<Self, ()> {
+        Self::with_policy(transport, RetryPolicy::default(), options)
+    }
+
+    /// Creates a new [`RetryTransport`] wrapping `transport` with the given
+    /// `policy`.
+    ///
+    /// # Errors
+    /// Fails if the retry thread couldn't be spawned.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_policy<T: Transport>(
+        transport: T,
+        policy: RetryPolicy,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        Self::spawn(Box::new(transport), policy, options.debug()).map_err(|_| ())
+    }
+
+    /// Spawns the retry thread.
+    fn spawn(transport: Box<dyn Transport>, policy: RetryPolicy, debug: bool) -> io::Result<Self> {
+        let transport = Arc::new(transport);
+        let shared = Arc::new(Shared {
+            policy,
+            debug,
+            state: Mutex::new(State::default()),
+            condvar: Condvar::new(),
+        });
+
+        let thread = Builder::new().name("sentry-retry".into()).spawn({
+            let transport = Arc::clone(&transport);
+            let shared = Arc::clone(&shared);
+            move || shared.run(&**transport)
+        })?;
+
+        Ok(Self {
+            transport,
+            shared,
+            thread,
+        })
+    }
+}
+
+impl Transport for RetryTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        {
+            let mut state = self.shared.lock();
+
+            // while the circuit is half-open only a single envelope is let through
+            match state.circuit(&self.shared.policy, Instant::now()) {
+                Circuit::Closed => (),
+                Circuit::HalfOpen if state.in_flight == 0 => (),
+                Circuit::Open | Circuit::HalfOpen => {
+                    drop(state);
+                    statistics().record_dropped(DropReason::Other);
+                    envelope.report_failure();
+
+                    if self.shared.debug {
+                        eprintln!(
+                            "[sentry-contrib-native] envelope dropped because the circuit is open"
+                        );
+                    }
+
+                    return;
+                }
+            }
+
+            state.in_flight += 1;
+        }
+
+        let entry = Entry {
+            envelope: envelope.serialize(),
+            attempt: 0,
+        };
+        self.transport.send(self.shared.attempt(entry));
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        let start = Instant::now();
+        let deadline = start + timeout;
+
+        let in_flight = {
+            let mut state = self.shared.lock();
+            state.deadline = Some(deadline);
+            self.shared.condvar.notify_all();
+
+            // keep retrying until everything was delivered
+            let mut state = self.shared.wait_idle(state, deadline);
+            state.stop = true;
+            self.shared.condvar.notify_all();
+
+            state.in_flight > 0
+        };
+
+        let _result = self.thread.join();
+
+        // give up on all remaining retries
+        let (pending, timed_out) = {
+            let mut state = self.shared.lock();
+            let pending = mem::take(&mut state.pending);
+            let timed_out = state.timed_out || !pending.is_empty() || in_flight;
+
+            (pending, timed_out)
+        };
+
+        for (_, entry) in pending {
+            self.shared.give_up(entry);
+        }
+
+        let result = match Arc::try_unwrap(self.transport) {
+            Ok(transport) => transport.shutdown(timeout.saturating_sub(start.elapsed())),
+            Err(_) => TransportShutdown::TimedOut,
+        };
+
+        if timed_out {
+            TransportShutdown::TimedOut
+        } else {
+            result
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        let deadline = start + timeout;
+
+        // retries keep their schedule, so this times out if one is due after the deadline
+        let idle = {
+            let state = self.shared.wait_idle(self.shared.lock(), deadline);
+            state.pending.is_empty() && state.in_flight == 0
+        };
+
+        // give the wrapped transport the time left over
+        let flushed = self
+            .transport
+            .flush(timeout.saturating_sub(start.elapsed()));
+
+        idle && flushed
+    }
+}
+
+/// State of the circuit breaker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Circuit {
+    /// Envelopes are sent.
+    Closed,
+    /// Envelopes are dropped.
+    Open,
+    /// A single envelope is let through to probe the service.
+    HalfOpen,
+}
+
+/// Envelope waiting for its delivery or retry.
+struct Entry {
+    /// Envelope passed to [`RetryTransport`], its failure is reported once the
+    /// envelope is given up.
+    envelope: Envelope,
+    /// Number of retries so far.
+    attempt: u32,
+}
+
+/// State shared between [`RetryTransport`], its thread and delivery
+/// callbacks.
+struct Shared {
+    /// Configuration of the retries.
+    policy: RetryPolicy,
+    /// Print envelopes that are given up to stderr.
+    debug: bool,
+    /// Mutable state.
+    state: Mutex<State>,
+    /// Notifies about changes to [`Shared::state`].
+    condvar: Condvar,
+}
+
+impl Debug for Shared {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("Shared")
+            .field("policy", &self.policy)
+            .field("debug", &self.debug)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Mutable part of [`Shared`].
+#[derive(Default)]
+struct State {
+    /// Envelopes waiting for their retry, with the time they are due.
+    pending: Vec<(Instant, Entry)>,
+    /// Number of envelopes passed to the wrapped [`Transport`] that weren't
+    /// delivered or failed yet.
+    in_flight: usize,
+    /// Number of consecutive failures.
+    failures: u32,
+    /// Time until the circuit is open.
+    open_until: Option<Instant>,
+    /// Deadline of [`Transport::shutdown`], retries due after it are given
+    /// up.
+    deadline: Option<Instant>,
+    /// Signals the retry thread to stop.
+    stop: bool,
+    /// If envelopes were given up because of the deadline.
+    timed_out: bool,
+}
+
+impl State {
+    /// State of the circuit breaker at `now`.
+    fn circuit(&self, policy: &RetryPolicy, now: Instant) -> Circuit {
+        match self.open_until {
+            Some(open_until) if open_until > now => Circuit::Open,
+            Some(_) if self.failures >= policy.failure_threshold => Circuit::HalfOpen,
+            _ => Circuit::Closed,
+        }
+    }
+}
+
+impl Shared {
+    /// Locks [`Shared::state`].
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("lock poisoned")
+    }
+
+    /// Waits until all envelopes were delivered or given up, or `deadline`
+    /// passed.
+    fn wait_idle<'a>(
+        &self,
+        mut state: MutexGuard<'a, State>,
+        deadline: Instant,
+    ) -> MutexGuard<'a, State> {
+        while !state.pending.is_empty() || state.in_flight > 0 {
+            let now = Instant::now();
+
+            if now >= deadline {
+                break;
+            }
+
+            state = self
+                .condvar
+                .wait_timeout(state, deadline - now)
+                .expect("lock poisoned")
+                .0;
+        }
+
+        state
+    }
+
+    /// Creates a [`RawEnvelope`] from `entry` to be passed to the wrapped
+    /// [`Transport`], which reports back once it was delivered or failed.
+    fn attempt(self: &Arc<Self>, entry: Entry) -> RawEnvelope {
+        let mut envelope = RawEnvelope::from(Envelope::from(entry.envelope.as_bytes().to_vec()));
+        let shared = Arc::clone(self);
+        envelope.on_delivery(move |delivered| shared.delivered(entry, delivered));
+
+        envelope
+    }
+
+    /// Handles the delivery result of `entry`.
+    fn delivered(&self, mut entry: Entry, delivered: bool) {
+        let mut state = self.lock();
+        state.in_flight -= 1;
+        let now = Instant::now();
+
+        if delivered {
+            state.failures = 0;
+            state.open_until = None;
+        } else {
+            state.failures = state.failures.saturating_add(1);
+
+            if state.failures >= self.policy.failure_threshold {
+                state.open_until = Some(now + self.policy.open_duration);
+            }
+
+            entry.attempt += 1;
+            let due = now + self.policy.backoff(entry.attempt);
+            let late = state.stop || state.deadline.map_or(false, |deadline| due > deadline);
+
+            if entry.attempt <= self.policy.max_retries && !late {
+                state.pending.push((due, entry));
+            } else {
+                state.timed_out |= late && entry.attempt <= self.policy.max_retries;
+                drop(state);
+                self.give_up(entry);
+                self.condvar.notify_all();
+
+                return;
+            }
+        }
+
+        self.condvar.notify_all();
+    }
+
+    /// Reports `entry` as failed.
+    fn give_up(&self, entry: Entry) {
+        entry.envelope.report_failure();
+        let attempt = entry.attempt;
+        // notifies the listeners of the envelope
+        drop(entry);
+
+        if self.debug {
+            eprintln!(
+                "[sentry-contrib-native] envelope given up after {} retries",
+                attempt.saturating_sub(1)
+            );
+        }
+    }
+
+    /// Sends retries to `transport` when they are due, until stopped.
+    fn run(self: Arc<Self>, transport: &dyn Transport) {
+        let mut state = self.lock();
+
+        while !state.stop {
+            let now = Instant::now();
+            let circuit = state.circuit(&self.policy, now);
+            let open_until = state.open_until;
+            // retries are postponed while the circuit is open
+            let ready_at = |due: Instant| match (circuit, open_until) {
+                (Circuit::Open, Some(open_until)) => due.max(open_until),
+                _ => due,
+            };
+
+            let mut due = Vec::new();
+            let mut given_up = Vec::new();
+            let mut wake = None;
+            let mut index = 0;
+
+            while index < state.pending.len() {
+                let ready = ready_at(state.pending[index].0);
+
+                if state.deadline.map_or(false, |deadline| ready > deadline) {
+                    given_up.push(state.pending.swap_remove(index).1);
+                } else if ready <= now
+                    && (circuit == Circuit::Closed || (due.is_empty() && state.in_flight == 0))
+                {
+                    due.push(state.pending.swap_remove(index).1);
+                } else {
+                    wake = Some(wake.map_or(ready, |wake: Instant| wake.min(ready)));
+                    index += 1;
+                }
+            }
+
+            if due.is_empty() && given_up.is_empty() {
+                state = match wake {
+                    Some(wake) => {
+                        self.condvar
+                            .wait_timeout(state, wake.saturating_duration_since(now))
+                            .expect("lock poisoned")
+                            .0
+                    }
+                    None => self.condvar.wait(state).expect("lock poisoned"),
+                };
+
+                continue;
+            }
+
+            state.in_flight += due.len();
+            state.timed_out |= !given_up.is_empty();
+            drop(state);
+
+            for entry in given_up {
+                self.give_up(entry);
+            }
+
+            for entry in due {
+                transport.send(self.attempt(entry));
+            }
+
+            self.condvar.notify_all();
+            state = self.lock();
+        }
+    }
+}
+
+/// Fails the first `fail` deliveries.
+#[cfg(test)]
+struct Failing {
+    /// Number of deliveries to fail.
+    fail: usize,
+    /// Number of deliveries so far.
+    sent: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+impl Failing {
+    /// Spawns a [`RetryTransport`] with `policy` wrapping a [`Failing`].
+    /// Returns the number of deliveries so far.
+    fn spawn(
+        fail: usize,
+        policy: RetryPolicy,
+    ) -> (RetryTransport, Arc<std::sync::atomic::AtomicUsize>) {
+        let sent = Arc::default();
+        let failing = Self {
+            fail,
+            sent: Arc::clone(&sent),
+        };
+
+        (
+            RetryTransport::spawn(Box::new(failing), policy, false).unwrap(),
+            sent,
+        )
+    }
+}
+
+#[cfg(test)]
+impl Transport for Failing {
+    fn send(&self, envelope: RawEnvelope) {
+        if self.sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < self.fail {
+            envelope.report_failure();
+        }
+    }
+
+    fn shutdown(self: Box<Self>, _: Duration) -> TransportShutdown {
+        TransportShutdown::Success
+    }
+}
+
+/// [`RetryPolicy`] with short delays for tests.
+#[cfg(test)]
+const TEST_POLICY: RetryPolicy = RetryPolicy {
+    max_retries: 3,
+    initial_backoff: Duration::from_millis(1),
+    max_backoff: Duration::from_millis(4),
+    jitter: 0.5,
+    failure_threshold: 10,
+    open_duration: Duration::from_secs(60),
+};
+
+/// Sends an envelope through `transport`, returns its delivery result.
+#[cfg(test)]
+fn send_test(transport: &RetryTransport) -> Arc<Mutex<Option<bool>>> {
+    let result = Arc::new(Mutex::new(None));
+    let mut envelope = RawEnvelope::from(Envelope::from(b"{}".to_vec()));
+    envelope.on_delivery({
+        let result = Arc::clone(&result);
+        move |delivered| *result.lock().unwrap() = Some(delivered)
+    });
+    transport.send(envelope);
+
+    result
+}
+
+#[test]
+fn retry() {
+    use std::sync::atomic::Ordering;
+
+    for attempt in 1..=4 {
+        let backoff = TEST_POLICY.backoff(attempt);
+        assert!(backoff <= Duration::from_millis(1 << (attempt - 1).min(2)));
+        assert!(backoff >= Duration::from_micros(500 << (attempt - 1).min(2)));
+    }
+
+    // delivered after retries
+    let (transport, sent) = Failing::spawn(2, TEST_POLICY);
+    let result = send_test(&transport);
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_secs(10))
+    );
+    assert_eq!(3, sent.load(Ordering::SeqCst));
+    assert_eq!(Some(true), *result.lock().unwrap());
+
+    // given up after `max_retries`
+    let (transport, sent) = Failing::spawn(usize::MAX, TEST_POLICY);
+    let result = send_test(&transport);
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_secs(10))
+    );
+    assert_eq!(4, sent.load(Ordering::SeqCst));
+    assert_eq!(Some(false), *result.lock().unwrap());
+
+    // retries due after the shutdown deadline are given up
+    let slow = RetryPolicy {
+        initial_backoff: Duration::from_secs(60),
+        max_backoff: Duration::from_secs(60),
+        jitter: 0.,
+        ..TEST_POLICY
+    };
+    let (transport, sent) = Failing::spawn(usize::MAX, slow);
+    let result = send_test(&transport);
+    let start = Instant::now();
+    assert_eq!(
+        TransportShutdown::TimedOut,
+        Box::new(transport).shutdown(Duration::from_secs(1))
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(1, sent.load(Ordering::SeqCst));
+    assert_eq!(Some(false), *result.lock().unwrap());
+}
+
+#[test]
+fn circuit() {
+    use std::{sync::atomic::Ordering, thread};
+
+    let policy = RetryPolicy {
+        failure_threshold: 2,
+        open_duration: Duration::from_millis(100),
+        ..TEST_POLICY
+    };
+    let (transport, sent) = Failing::spawn(2, policy);
+    let first = send_test(&transport);
+
+    // the first envelope was retried once before the circuit opened
+    while transport.shared.lock().open_until.is_none() {
+        thread::yield_now();
+    }
+    assert_eq!(2, sent.load(Ordering::SeqCst));
+
+    // envelopes are dropped while the circuit is open
+    let second = send_test(&transport);
+    assert_eq!(Some(false), *second.lock().unwrap());
+    assert_eq!(2, sent.load(Ordering::SeqCst));
+
+    // the postponed retry probes the service and closes the circuit
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_secs(10))
+    );
+    assert_eq!(3, sent.load(Ordering::SeqCst));
+    assert_eq!(Some(true), *first.lock().unwrap());
+
+    // the postponed retry is given up if the circuit is still open on shutdown
+    let policy = RetryPolicy {
+        open_duration: Duration::from_secs(60),
+        ..policy
+    };
+    let (transport, _) = Failing::spawn(usize::MAX, policy);
+    let result = send_test(&transport);
+    while transport.shared.lock().open_until.is_none() {
+        thread::yield_now();
+    }
+    assert_eq!(
+        TransportShutdown::TimedOut,
+        Box::new(transport).shutdown(Duration::from_millis(100))
+    );
+    assert_eq!(Some(false), *result.lock().unwrap());
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn retry_transport() -> anyhow::Result<()> {
+    use crate::{test, Event};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SEND: AtomicUsize = AtomicUsize::new(0);
+
+    test::set_hook();
+
+    let mut options = Options::new();
+    options.set_transport(|options| {
+        RetryTransport::with_policy(
+            |envelope: RawEnvelope| {
+                // fail every second delivery
+                if SEND.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                    envelope.report_failure();
+                }
+            },
+            TEST_POLICY,
+            options,
+        )
+    });
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    assert!(SEND.load(Ordering::SeqCst) > 3);
+
+    test::verify_panics();
+
+    Ok(())
+}
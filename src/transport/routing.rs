@@ -0,0 +1,264 @@
+//! Transport sending every envelope to a destination picked per event.
+
+use super::mirror::{self, Destination};
+use crate::{Dsn, Options, RawEnvelope, Transport, TransportShutdown, Value};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    iter,
+    time::Duration,
+};
+
+/// Function picking the [`Dsn`] of an event, see [`RoutingTransport::new`].
+type Router = Box<dyn Fn(&Value) -> Option<Dsn> + 'static + Send + Sync>;
+
+/// [`Transport`] sending every envelope to a destination picked by a routing
+/// function, e.g. to attribute crashes in plugins to the project of the team
+/// owning them.
+///
+/// The routing function is called with the event of every envelope, see
+/// [`RawEnvelope::event`], which is [`Value::Null`] for envelopes without
+/// one, like sessions. It can inspect everything in the event, like tags,
+/// modules, the fingerprint or the release, and returns the [`Dsn`] of a
+/// destination added with [`RoutingTransport::destination`]. Envelopes are
+/// sent to the DSN configured with [`Options::set_dsn`] if it returns
+/// [`None`] or a [`Dsn`] without a destination.
+///
+/// Envelopes sent to another destination have the `dsn` in their envelope
+/// header replaced. The [`Transport`] of a destination has to be configured to
+/// send to that [`Dsn`] too.
+///
+/// [`Transport::flush`] and [`Transport::shutdown`] are passed on to all
+/// destinations at the same time, each with the full timeout.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # #[cfg(feature = "transport-rustls")]
+/// # {
+/// # use sentry_contrib_native::{Dsn, Event, Options, RoutingTransport, RustlsTransport, Value};
+/// const PLUGIN_DSN: &str = "https://public_key_5678@organization_1234.ingest.sentry.io/project_id_5678";
+///
+/// let mut options = Options::new();
+/// options.set_dsn("https://public_key_1234@organization_1234.ingest.sentry.io/project_id_1234");
+/// options.set_transport(|options| {
+///     let mut plugin = Options::new();
+///     plugin.set_dsn(PLUGIN_DSN);
+///
+///     Ok(RoutingTransport::new(
+///         RustlsTransport::new(options)?,
+///         |event: &Value| {
+///             // events tagged by the plugin go to its own project
+///             let tags = event.as_map()?.get("tags")?.as_map()?;
+///
+///             if tags.get("plugin")?.as_str()? == "example" {
+///                 Dsn::new(PLUGIN_DSN).ok()
+///             } else {
+///                 None
+///             }
+///         },
+///         options,
+///     )?
+///     .destination(
+///         Dsn::new(PLUGIN_DSN).map_err(|_| ())?,
+///         RustlsTransport::new(&plugin)?,
+///     ))
+/// });
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # }
+/// # Ok(()) }
+/// ```
+pub struct RoutingTransport {
+    /// Function picking the [`Dsn`] of an event.
+    router: Router,
+    /// Destination of the DSN configured with [`Options::set_dsn`].
+    default: Destination,
+    /// Destinations that can be picked by the routing function.
+    destinations: Vec<Destination>,
+    /// Print envelopes routed to a [`Dsn`] without a destination to stderr.
+    debug: bool,
+}
+
+impl Debug for RoutingTransport {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+        fmt.debug_struct("RoutingTransport")
+            .field("default", &self.default)
+            .field("destinations", &self.destinations)
+            .field("debug", &self.debug)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RoutingTransport {
+    /// Creates a new [`RoutingTransport`] sending envelopes with `transport`
+    /// to the DSN configured in `options`, unless `router` picks the [`Dsn`]
+    /// of another destination.
+    ///
+    /// If [`Options::debug`] is enabled, envelopes routed to a [`Dsn`] without
+    /// a destination are printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if no valid DSN was configured.
+    #[allow(clippy::result_unit_err)]
+    pub fn new<T: Transport, R: Fn(&Value) -> Option<Dsn> + 'static + Send + Sync>(
+        transport: T,
+        router: R,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        let dsn = options.dsn().and_then(|dsn| Dsn::new(dsn).ok()).ok_or(())?;
+
+        Ok(Self {
+            router: Box::new(router),
+            default: Destination::new(dsn, transport),
+            destinations: Vec::new(),
+            debug: options.debug(),
+        })
+    }
+
+    /// Adds a destination that can be picked by the routing function, sending
+    /// envelopes with `transport`, which has to be configured to send to
+    /// `dsn`.
+    #[must_use]
+    pub fn destination<T: Transport>(mut self, dsn: Dsn, transport: T) -> Self {
+        self.destinations.push(Destination::new(dsn, transport));
+
+        self
+    }
+
+    /// Picks the [`Destination`] of `envelope`, [`None`] for the default one.
+    fn route(&self, envelope: &RawEnvelope) -> Option<&Destination> {
+        let dsn = (self.router)(&envelope.event())?;
+
+        if dsn == self.default.dsn {
+            return None;
+        }
+
+        let destination = self
+            .destinations
+            .iter()
+            .find(|destination| destination.dsn == dsn);
+
+        if destination.is_none() && self.debug {
+            eprintln!(
+                "[sentry-contrib-native] no destination for routed DSN {}, sending to the default DSN",
+                dsn
+            );
+        }
+
+        destination
+    }
+}
+
+impl Transport for RoutingTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        if let Some(destination) = self.route(&envelope) {
+            let original = envelope.serialize();
+            let mut copy = destination.prepare(original.as_bytes());
+            copy.on_delivery(move |delivered| {
+                if !delivered {
+                    original.report_failure();
+                }
+            });
+
+            destination.transport.send(copy);
+        } else {
+            self.default.transport.send(envelope);
+        }
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        mirror::shutdown_all(iter::once(self.default).chain(self.destinations), timeout)
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        mirror::flush_all(iter::once(&self.default).chain(&self.destinations), timeout)
+    }
+}
+
+#[test]
+fn routing() -> anyhow::Result<()> {
+    use crate::Envelope;
+    use std::sync::{Arc, Mutex};
+
+    /// Records envelopes.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Transport for Recorder {
+        fn send(&self, envelope: RawEnvelope) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(envelope.serialize().as_bytes().to_vec());
+        }
+    }
+
+    /// Builds an envelope with an event tagged with `team`.
+    fn envelope(team: &str) -> RawEnvelope {
+        let event = format!("{{\"tags\":{{\"team\":\"{}\"}}}}", team);
+
+        RawEnvelope::from(Envelope::from(
+            format!(
+                "{{\"dsn\":\"https://default@sentry.io/1\"}}\n{{\"type\":\"event\",\"length\":{}}}\n{}\n",
+                event.len(),
+                event
+            )
+            .into_bytes(),
+        ))
+    }
+
+    let default = Recorder::default();
+    let audio = Recorder::default();
+
+    let mut options = Options::new();
+    options.set_dsn("https://default@sentry.io/1");
+    let transport = RoutingTransport::new(
+        default.clone(),
+        |event: &Value| match event
+            .as_map()?
+            .get("tags")?
+            .as_map()?
+            .get("team")?
+            .as_str()?
+        {
+            "audio" => Dsn::new("https://audio@sentry.io/2").ok(),
+            "video" => Dsn::new("https://video@sentry.io/3").ok(),
+            _ => None,
+        },
+        &options,
+    )
+    .map_err(|_| anyhow::anyhow!("no DSN"))?
+    .destination(Dsn::new("https://audio@sentry.io/2")?, audio.clone());
+
+    transport.send(envelope("audio"));
+    // no destination for the video team
+    transport.send(envelope("video"));
+    transport.send(envelope("other"));
+    transport.send(RawEnvelope::from(Envelope::from(b"{}\n".to_vec())));
+
+    let audio = audio.0.lock().unwrap();
+    assert_eq!(1, audio.len());
+    assert!(audio[0].starts_with(b"{\"dsn\":\"https://audio@sentry.io/2\"}\n"));
+    // items are untouched
+    let items = |envelope: &[u8]| {
+        envelope
+            .splitn(2, |byte| *byte == b'\n')
+            .nth(1)
+            .unwrap()
+            .to_vec()
+    };
+    assert_eq!(
+        items(envelope("audio").serialize().as_bytes()),
+        items(&audio[0])
+    );
+
+    let default = default.0.lock().unwrap();
+    assert_eq!(3, default.len());
+    assert!(default
+        .iter()
+        .take(2)
+        .all(|envelope| envelope.starts_with(b"{\"dsn\":\"https://default@sentry.io/1\"}\n")));
+
+    Ok(())
+}
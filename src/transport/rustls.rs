@@ -0,0 +1,251 @@
+//! Built-in blocking HTTP transport based on [`ureq`] and `rustls`.
+
+use super::worker::Worker;
+use crate::{
+    statistics, Compression, DropReason, Dsn, Options, RateLimiter, RawEnvelope, Request,
+    Transport, TransportShutdown,
+};
+use ::{
+    rustls::{ClientConfig, RootCertStore},
+    std::{sync::Arc, time::Duration},
+    ureq::{Agent, AgentBuilder, Error as UreqError, Proxy, Response},
+};
+
+/// Pure-Rust [`Transport`] that sends envelopes over HTTPS with `rustls`,
+/// without depending on any C HTTP library.
+///
+/// Envelopes are enqueued and sent one after another by a dedicated worker
+/// thread, keeping them in order. Rate limits received from the Sentry service
+/// are honored with a [`RateLimiter`]. [`Transport::flush`] and
+/// [`Transport::shutdown`] wait for the queue to be emptied until the given
+/// timeout is reached.
+///
+/// The proxy and CA certificates configured in [`Options`] are applied, see
+/// [`Options::http_config`].
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{Event, Options, RustlsTransport};
+/// let mut options = Options::new();
+/// options.set_dsn("https://public_key_1234@organization_1234.ingest.sentry.io/project_id_1234");
+/// options.set_transport(RustlsTransport::new);
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct RustlsTransport {
+    /// Worker sending the envelopes.
+    worker: Worker,
+}
+
+impl RustlsTransport {
+    /// Maximum number of envelopes waiting to be sent, further envelopes are
+    /// dropped.
+    pub const QUEUE_CAPACITY: usize = 1024;
+    /// Timeout for a single request.
+    pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Creates a new [`RustlsTransport`] sending to the DSN configured in
+    /// `options`. Intended to be passed to [`Options::set_transport`].
+    ///
+    /// If [`Options::debug`] is enabled, failed requests and envelopes dropped
+    /// because of rate limits are printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if no valid DSN, proxy or CA certificates were configured or the
+    /// worker thread couldn't be spawned.
+    #[allow(clippy::result_unit_err)]
+    pub fn new(options: &Options) -> Result<Self, ()> {
+        Self::with_compression(Compression::default(), options)
+    }
+
+    /// Creates a new [`RustlsTransport`] like [`RustlsTransport::new`],
+    /// compressing request bodies according to `compression`.
+    ///
+    /// # Errors
+    /// Fails if no valid DSN, proxy or CA certificates were configured or the
+    /// worker thread couldn't be spawned.
+    ///
+    /// # Examples
+    /// ```
+    /// # fn main() -> anyhow::Result<()> {
+    /// # #[cfg(feature = "compression-gzip")]
+    /// # {
+    /// # use sentry_contrib_native::{Compression, Encoding, Options, RustlsTransport};
+    /// let mut options = Options::new();
+    /// options.set_transport(|options| {
+    ///     RustlsTransport::with_compression(Compression::new(Encoding::Gzip), options)
+    /// });
+    /// let _shutdown = options.init()?;
+    /// # }
+    /// # Ok(()) }
+    /// ```
+    #[allow(clippy::result_unit_err)]
+    pub fn with_compression(compression: Compression, options: &Options) -> Result<Self, ()> {
+        let dsn = options.dsn().and_then(|dsn| Dsn::new(dsn).ok()).ok_or(())?;
+        let debug = options.debug();
+        let agent = agent(&dsn, options)?;
+        let mut limiter = RateLimiter::new();
+
+        let worker = Worker::new(
+            "sentry-rustls-transport",
+            Self::QUEUE_CAPACITY,
+            move |envelope| {
+                let serialized = match limiter.filter(envelope.serialize()) {
+                    Some(serialized) => serialized,
+                    None => {
+                        statistics().record_dropped(DropReason::RateLimited);
+
+                        if debug {
+                            eprintln!(
+                                "[sentry-contrib-native] envelope dropped because of rate limits"
+                            );
+                        }
+
+                        return;
+                    }
+                };
+
+                let request = serialized.into_compressed_request(dsn.clone(), compression);
+                let bytes = request.body().as_bytes().len();
+
+                match send(&agent, request) {
+                    Ok(response) => {
+                        update(&mut limiter, &response);
+                        statistics().record_sent(bytes);
+                    }
+                    Err(error) => {
+                        match &*error {
+                            UreqError::Status(status, response) => {
+                                update(&mut limiter, response);
+
+                                if *status == 429 {
+                                    statistics().record_dropped(DropReason::RateLimited);
+                                } else {
+                                    statistics().record_failed();
+                                }
+
+                                if *status >= 500 {
+                                    envelope.report_failure();
+                                }
+                            }
+                            UreqError::Transport(_) => {
+                                statistics().record_failed();
+                                envelope.report_failure();
+                            }
+                        }
+
+                        if debug {
+                            eprintln!("[sentry-contrib-native] failed to send envelope: {}", error);
+                        }
+                    }
+                }
+            },
+        )
+        .map_err(|_| ())?;
+
+        Ok(Self { worker })
+    }
+}
+
+impl Transport for RustlsTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        self.worker.enqueue(envelope);
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        self.worker.flush(timeout)
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        self.worker.shutdown(timeout)
+    }
+}
+
+/// Builds the [`Agent`] sending to `dsn` with the proxy and CA certificates
+/// configured in `options`.
+fn agent(dsn: &Dsn, options: &Options) -> Result<Agent, ()> {
+    let config = options.http_config().map_err(|_| ())?;
+    let mut builder = AgentBuilder::new().timeout(RustlsTransport::REQUEST_TIMEOUT);
+
+    if let Some(proxy) = config.proxy_for(dsn.host()) {
+        builder = builder.proxy(Proxy::new(proxy.url().as_str()).map_err(|_| ())?);
+    }
+
+    if config.ca_certs().is_some() {
+        let mut roots = RootCertStore::empty();
+        let (added, _) = roots.add_parsable_certificates(&config.load_ca_certs().map_err(|_| ())?);
+
+        if added == 0 {
+            return Err(());
+        }
+
+        let tls = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        builder = builder.tls_config(Arc::new(tls));
+    }
+
+    Ok(builder.build())
+}
+
+/// Sends `request` with `agent`. Error status codes are returned as
+/// [`UreqError::Status`].
+fn send(agent: &Agent, request: Request) -> Result<Response, Box<UreqError>> {
+    let (parts, body) = request.into_parts();
+    let mut request = agent.request(parts.method.as_str(), &parts.uri.to_string());
+
+    for (name, value) in &parts.headers {
+        // `ureq` calculates the content length itself
+        if name == "content-length" {
+            continue;
+        }
+
+        if let Ok(value) = value.to_str() {
+            request = request.set(name.as_str(), value);
+        }
+    }
+
+    request.send_bytes(body.as_bytes()).map_err(Box::new)
+}
+
+/// Updates `limiter` with the rate limits in `response`.
+fn update(limiter: &mut RateLimiter, response: &Response) {
+    limiter.update(
+        response.status(),
+        response.header("x-sentry-rate-limits"),
+        response.header("retry-after"),
+    );
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn rustls() -> anyhow::Result<()> {
+    use crate::{test, Event};
+
+    test::set_hook();
+
+    let mut options = Options::new();
+    options.set_transport(RustlsTransport::new);
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    // sessions may be sent in addition to the events
+    let statistics = statistics();
+    assert!(statistics.sent() >= 3);
+    assert_eq!(0, statistics.failed());
+    assert_eq!(0, statistics.queue_depth());
+
+    test::verify_panics();
+
+    Ok(())
+}
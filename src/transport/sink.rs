@@ -0,0 +1,398 @@
+//! Transport writing envelopes as JSON lines to a local sink.
+
+use super::{envelope::split, worker::Worker};
+use crate::{statistics, DropReason, Options, RawEnvelope, Transport, TransportShutdown};
+#[cfg(unix)]
+use std::{
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+use ::{
+    serde_json::{Map as JsonMap, Value as JsonValue},
+    std::{
+        io::{self, Write},
+        time::Duration,
+    },
+};
+
+/// Destination of a [`SinkTransport`].
+#[derive(Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Sink {
+    /// Write to the standard output.
+    Stdout,
+    /// Write to the standard error.
+    Stderr,
+    /// Write to the Unix domain socket at the given path, reconnecting if the
+    /// connection was lost.
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
+}
+
+/// Determines what a [`SinkTransport`] writes for every envelope.
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub enum SinkFormat {
+    /// A JSON object with the `header` of the envelope and its `items`, each
+    /// with its `header` and its `payload`. Payloads that aren't JSON, like
+    /// attachments, are written as `payload_base64` instead.
+    Envelope,
+    /// Only the payloads of event items, envelopes without an event are
+    /// skipped.
+    Events,
+}
+
+impl Default for SinkFormat {
+    fn default() -> Self {
+        Self::Envelope
+    }
+}
+
+/// [`Transport`] writing envelopes as JSON lines to the standard output, the
+/// standard error or a Unix domain socket instead of sending them, e.g. to a
+/// local agent forwarding them to the Sentry service.
+///
+/// Every envelope is converted into a single line of JSON according to
+/// [`SinkFormat`], followed by a newline. Lines are written one after another
+/// by a dedicated worker thread, keeping them in order. If writing to a Unix
+/// domain socket fails, it reconnects and tries again once, otherwise the
+/// envelope is reported as failed, see [`RawEnvelope::on_delivery`].
+///
+/// No DSN is required.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{Event, Options, Sink, SinkTransport};
+/// let mut options = Options::new();
+/// options.set_transport(|options| SinkTransport::new(Sink::Stdout, options));
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct SinkTransport {
+    /// Worker writing the envelopes.
+    worker: Worker,
+}
+
+impl SinkTransport {
+    /// Maximum number of envelopes waiting to be written, further envelopes
+    /// are dropped.
+    pub const QUEUE_CAPACITY: usize = 1024;
+    /// Timeout for writing a single line to a Unix domain socket.
+    pub const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Creates a new [`SinkTransport`] writing envelopes to `sink` in the
+    /// [`SinkFormat::Envelope`] format. Intended to be passed to
+    /// [`Options::set_transport`].
+    ///
+    /// If [`Options::debug`] is enabled, envelopes that couldn't be written
+    /// are printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if the worker thread couldn't be spawned.
+    #[allow(clippy::result_unit_err)]
+    pub fn new(sink: Sink, options: &Options) -> Result<Self, ()> {
+        Self::with_format(sink, SinkFormat::default(), options)
+    }
+
+    /// Creates a new [`SinkTransport`] like [`SinkTransport::new`], writing
+    /// envelopes in the given `format`.
+    ///
+    /// # Errors
+    /// Fails if the worker thread couldn't be spawned.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_format(sink: Sink, format: SinkFormat, options: &Options) -> Result<Self, ()> {
+        let debug = options.debug();
+        let mut output = Output::from(sink);
+
+        let worker = Worker::new("sentry-sink", Self::QUEUE_CAPACITY, move |envelope| {
+            let serialized = envelope.serialize();
+
+            let lines = match to_lines(serialized.as_bytes(), format) {
+                Some(lines) => lines,
+                None => {
+                    statistics().record_dropped(DropReason::Other);
+
+                    if debug {
+                        eprintln!(
+                            "[sentry-contrib-native] envelope dropped because it's malformed"
+                        );
+                    }
+
+                    return;
+                }
+            };
+
+            if lines.is_empty() {
+                return;
+            }
+
+            match output.write(&lines) {
+                Ok(()) => statistics().record_sent(lines.len()),
+                Err(error) => {
+                    statistics().record_failed();
+                    envelope.report_failure();
+
+                    if debug {
+                        eprintln!(
+                            "[sentry-contrib-native] failed to write envelope: {}",
+                            error
+                        );
+                    }
+                }
+            }
+        })
+        .map_err(|_| ())?;
+
+        Ok(Self { worker })
+    }
+}
+
+impl Transport for SinkTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        self.worker.enqueue(envelope);
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        self.worker.shutdown(timeout)
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        self.worker.flush(timeout)
+    }
+}
+
+/// Output of a [`Sink`], holding the connection to a Unix domain socket.
+enum Output {
+    /// See [`Sink::Stdout`].
+    Stdout,
+    /// See [`Sink::Stderr`].
+    Stderr,
+    /// See [`Sink::UnixSocket`].
+    #[cfg(unix)]
+    UnixSocket {
+        /// Path of the socket.
+        path: PathBuf,
+        /// Connection, [`None`] until connected or after it failed.
+        stream: Option<UnixStream>,
+    },
+}
+
+impl From<Sink> for Output {
+    fn from(sink: Sink) -> Self {
+        match sink {
+            Sink::Stdout => Self::Stdout,
+            Sink::Stderr => Self::Stderr,
+            #[cfg(unix)]
+            Sink::UnixSocket(path) => Self::UnixSocket { path, stream: None },
+        }
+    }
+}
+
+impl Output {
+    /// Writes `lines` and flushes them.
+    fn write(&mut self, lines: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Stdout => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(lines)?;
+                stdout.flush()
+            }
+            Self::Stderr => {
+                let stderr = io::stderr();
+                let mut stderr = stderr.lock();
+                stderr.write_all(lines)?;
+                stderr.flush()
+            }
+            #[cfg(unix)]
+            Self::UnixSocket { path, stream } => {
+                // reconnect once if the connection was lost
+                let mut result = write_socket(path, stream, lines);
+
+                if result.is_err() {
+                    result = write_socket(path, stream, lines);
+                }
+
+                result
+            }
+        }
+    }
+}
+
+/// Writes `lines` to the Unix domain socket at `path`, connecting first if
+/// `stream` isn't connected. Drops the connection if it failed.
+#[cfg(unix)]
+fn write_socket(path: &Path, stream: &mut Option<UnixStream>, lines: &[u8]) -> io::Result<()> {
+    let result = match stream {
+        Some(stream) => stream.write_all(lines),
+        None => UnixStream::connect(path).and_then(|mut connected| {
+            connected.set_write_timeout(Some(SinkTransport::WRITE_TIMEOUT))?;
+            connected.write_all(lines)?;
+            stream.replace(connected);
+            Ok(())
+        }),
+    };
+
+    if result.is_err() {
+        stream.take();
+    }
+
+    result
+}
+
+/// Converts a serialized envelope into JSON lines according to `format`.
+/// Returns [`None`] if the envelope is malformed.
+fn to_lines(envelope: &[u8], format: SinkFormat) -> Option<Vec<u8>> {
+    let (header, items) = split(envelope).ok()?;
+    let mut lines = Vec::new();
+
+    match format {
+        SinkFormat::Envelope => {
+            let items = items
+                .into_iter()
+                .map(|(header, payload)| {
+                    let mut item = JsonMap::new();
+                    item.insert("header".into(), header.into());
+
+                    match serde_json::from_slice::<JsonValue>(payload) {
+                        Ok(payload) => item.insert("payload".into(), payload),
+                        Err(_) => item.insert("payload_base64".into(), base64(payload).into()),
+                    };
+
+                    JsonValue::from(item)
+                })
+                .collect::<Vec<_>>();
+
+            let mut envelope = JsonMap::new();
+            envelope.insert("header".into(), header.into());
+            envelope.insert("items".into(), items.into());
+
+            // JSON doesn't contain any newlines without pretty printing
+            serde_json::to_writer(&mut lines, &envelope).ok()?;
+            lines.push(b'\n');
+        }
+        SinkFormat::Events => {
+            for (header, payload) in items {
+                if header.get("type").and_then(JsonValue::as_str) != Some("event") {
+                    continue;
+                }
+
+                let event: JsonValue = serde_json::from_slice(payload).ok()?;
+                serde_json::to_writer(&mut lines, &event).ok()?;
+                lines.push(b'\n');
+            }
+        }
+    }
+
+    Some(lines)
+}
+
+/// Encodes `bytes` with the standard base64 alphabet and padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let bits = (u32::from(chunk[0]) << 16)
+            | (u32::from(chunk.get(1).copied().unwrap_or(0)) << 8)
+            | u32::from(chunk.get(2).copied().unwrap_or(0));
+
+        // every byte of the chunk spans into one more character
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (bits >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[test]
+fn lines() {
+    let envelope = b"{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\"}\n\
+        {\"type\":\"attachment\",\"length\":4}\n\
+        a\nb\xff\n\
+        {\"type\":\"event\",\"length\":20}\n\
+        {\"message\":\"a\\nb\"}  \n";
+
+    assert_eq!(
+        "{\"header\":{\"event_id\":\"9ec79c33ec9942ab8353589fcb2e04dc\"},\"items\":[\
+        {\"header\":{\"length\":4,\"type\":\"attachment\"},\"payload_base64\":\"YQpi/w==\"},\
+        {\"header\":{\"length\":20,\"type\":\"event\"},\"payload\":{\"message\":\"a\\nb\"}}]}\n",
+        String::from_utf8(to_lines(envelope, SinkFormat::Envelope).unwrap()).unwrap()
+    );
+    assert_eq!(
+        "{\"message\":\"a\\nb\"}\n",
+        String::from_utf8(to_lines(envelope, SinkFormat::Events).unwrap()).unwrap()
+    );
+    assert_eq!(
+        Some(Vec::new()),
+        to_lines(b"{}\n{\"type\":\"session\"}\n{}\n", SinkFormat::Events)
+    );
+    assert_eq!(None, to_lines(b"{", SinkFormat::Envelope));
+
+    assert_eq!("", base64(b""));
+    assert_eq!("Zg==", base64(b"f"));
+    assert_eq!("Zm8=", base64(b"fo"));
+    assert_eq!("Zm9v", base64(b"foo"));
+    assert_eq!("Zm9vYmFy", base64(b"foobar"));
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn unix_socket() -> anyhow::Result<()> {
+    use crate::Envelope;
+    use std::{
+        env, fs,
+        io::{BufRead, BufReader},
+        os::unix::net::UnixListener,
+        process,
+    };
+
+    let path = env::temp_dir().join(format!("sentry-contrib-native-sink-{}.sock", process::id()));
+    let _result = fs::remove_file(&path);
+
+    let envelope = || {
+        RawEnvelope::from(Envelope::from(
+            b"{}\n{\"type\":\"event\",\"length\":2}\n{}\n".to_vec(),
+        ))
+    };
+
+    let transport = SinkTransport::with_format(
+        Sink::UnixSocket(path.clone()),
+        SinkFormat::Events,
+        &Options::new(),
+    )
+    .map_err(|_| anyhow::anyhow!("failed to spawn worker"))?;
+
+    // fails without a listener
+    transport.send(envelope());
+    assert!(transport.flush(Duration::from_secs(1)));
+
+    // connects once the listener is up
+    let listener = UnixListener::bind(&path)?;
+    transport.send(envelope());
+    transport.send(envelope());
+    assert_eq!(
+        TransportShutdown::Success,
+        Box::new(transport).shutdown(Duration::from_secs(1))
+    );
+
+    let (stream, _) = listener.accept()?;
+    let lines = BufReader::new(stream)
+        .lines()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(vec!["{}", "{}"], lines);
+
+    fs::remove_file(&path)?;
+
+    Ok(())
+}
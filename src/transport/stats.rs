@@ -0,0 +1,299 @@
+//! Delivery statistics reported by transports.
+
+#[cfg(doc)]
+use crate::{BeforeSend, Options, RawEnvelope, Shutdown, Throttle, Transport};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters behind [`Statistics`], reset by [`Options::init`].
+static COUNTERS: Counters = Counters::new();
+
+/// Reason an envelope was dropped without being sent, see
+/// [`Statistics::record_dropped`].
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DropReason {
+    /// The queue of the transport was full.
+    QueueFull,
+    /// The envelope was dropped because of rate limits, either by the
+    /// transport itself or by the Sentry service with a `429` response.
+    RateLimited,
+    /// The event was discarded by [`BeforeSend`].
+    BeforeSend,
+    /// The event or session was suppressed by a [`Throttle`], see
+    /// [`Options::set_throttle`].
+    Throttled,
+    /// Any other reason, e.g. the envelope was too big.
+    Other,
+}
+
+/// Handle to the delivery statistics of the running [`Transport`], see
+/// [`statistics`] or [`Shutdown::statistics`].
+///
+/// All shipped transports report into these counters. Custom [`Transport`]s
+/// can report into them with [`Statistics::record_sent`] and the other
+/// `record_*` methods. The default transport of sentry-native doesn't report
+/// anything, only events discarded by [`BeforeSend`] are counted in that case.
+///
+/// Counters start at zero with every [`Options::init`] and can be read at any
+/// time, even after shutting down.
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{DropReason, Event, Options};
+/// let options = Options::new();
+/// let shutdown = options.init()?;
+///
+/// Event::new().capture();
+///
+/// let statistics = shutdown.statistics();
+/// println!(
+///     "sent: {}, failed: {}, rate limited: {}",
+///     statistics.sent(),
+///     statistics.failed(),
+///     statistics.dropped_by(DropReason::RateLimited)
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Statistics {
+    /// The global counters.
+    counters: &'static Counters,
+}
+
+/// Returns a handle to the delivery statistics, see [`Statistics`].
+#[must_use]
+pub fn statistics() -> Statistics {
+    Statistics {
+        counters: &COUNTERS,
+    }
+}
+
+impl Statistics {
+    /// Number of envelopes sent successfully.
+    #[must_use]
+    pub fn sent(self) -> u64 {
+        self.counters.sent.load(Ordering::Relaxed)
+    }
+
+    /// Number of envelopes that failed to be sent.
+    #[must_use]
+    pub fn failed(self) -> u64 {
+        self.counters.failed.load(Ordering::Relaxed)
+    }
+
+    /// Number of envelopes dropped for any reason.
+    #[must_use]
+    pub fn dropped(self) -> u64 {
+        [
+            DropReason::QueueFull,
+            DropReason::RateLimited,
+            DropReason::BeforeSend,
+            DropReason::Throttled,
+            DropReason::Other,
+        ]
+        .iter()
+        .map(|reason| self.dropped_by(*reason))
+        .sum()
+    }
+
+    /// Number of envelopes dropped because of `reason`.
+    #[must_use]
+    pub fn dropped_by(self, reason: DropReason) -> u64 {
+        self.counters.dropped(reason).load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes sent, the size of request bodies after compression.
+    #[must_use]
+    pub fn bytes(self) -> u64 {
+        self.counters.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of envelopes currently waiting in the queue of the transport.
+    #[must_use]
+    pub fn queue_depth(self) -> u64 {
+        self.counters.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Records an envelope sent successfully with `bytes` bytes.
+    pub fn record_sent(self, bytes: usize) {
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records an envelope that failed to be sent. Independent of
+    /// [`RawEnvelope::report_failure`].
+    pub fn record_failed(self) {
+        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an envelope dropped because of `reason`.
+    pub fn record_dropped(self, reason: DropReason) {
+        self.counters
+            .dropped(reason)
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an envelope added to the queue of the transport.
+    pub fn record_enqueued(self) {
+        self.counters.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an envelope taken out of the queue of the transport.
+    pub fn record_dequeued(self) {
+        self.counters.dequeue();
+    }
+}
+
+/// Resets all counters, called by [`Options::init`].
+pub(crate) fn reset() {
+    COUNTERS.reset();
+}
+
+/// Atomic counters of [`Statistics`].
+#[derive(Debug)]
+struct Counters {
+    /// Envelopes sent.
+    sent: AtomicU64,
+    /// Envelopes failed.
+    failed: AtomicU64,
+    /// Envelopes dropped with [`DropReason::QueueFull`].
+    queue_full: AtomicU64,
+    /// Envelopes dropped with [`DropReason::RateLimited`].
+    rate_limited: AtomicU64,
+    /// Envelopes dropped with [`DropReason::BeforeSend`].
+    before_send: AtomicU64,
+    /// Envelopes dropped with [`DropReason::Throttled`].
+    throttled: AtomicU64,
+    /// Envelopes dropped with [`DropReason::Other`].
+    other: AtomicU64,
+    /// Bytes sent.
+    bytes: AtomicU64,
+    /// Envelopes waiting in the queue.
+    queue_depth: AtomicU64,
+}
+
+impl Counters {
+    /// Creates new [`Counters`] starting at zero.
+    const fn new() -> Self {
+        Self {
+            sent: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            queue_full: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            before_send: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            other: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the counter for `reason`.
+    const fn dropped(&self, reason: DropReason) -> &AtomicU64 {
+        match reason {
+            DropReason::QueueFull => &self.queue_full,
+            DropReason::RateLimited => &self.rate_limited,
+            DropReason::BeforeSend => &self.before_send,
+            DropReason::Throttled => &self.throttled,
+            DropReason::Other => &self.other,
+        }
+    }
+
+    /// Decrements the queue depth, which can't go below zero if the counters
+    /// were reset while envelopes were still queued.
+    fn dequeue(&self) {
+        let _ = self
+            .queue_depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                depth.checked_sub(1)
+            });
+    }
+
+    /// Resets all counters to zero.
+    fn reset(&self) {
+        for counter in &[
+            &self.sent,
+            &self.failed,
+            &self.queue_full,
+            &self.rate_limited,
+            &self.before_send,
+            &self.throttled,
+            &self.other,
+            &self.bytes,
+            &self.queue_depth,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[test]
+fn counters() {
+    let counters = Counters::new();
+
+    counters.queue_depth.fetch_add(1, Ordering::Relaxed);
+    counters.dequeue();
+    counters.dequeue();
+    assert_eq!(0, counters.queue_depth.load(Ordering::Relaxed));
+
+    counters
+        .dropped(DropReason::RateLimited)
+        .fetch_add(1, Ordering::Relaxed);
+    assert_eq!(1, counters.rate_limited.load(Ordering::Relaxed));
+    assert_eq!(
+        0,
+        counters.dropped(DropReason::Other).load(Ordering::Relaxed)
+    );
+
+    counters.sent.fetch_add(1, Ordering::Relaxed);
+    counters.reset();
+    assert_eq!(0, counters.sent.load(Ordering::Relaxed));
+    assert_eq!(0, counters.rate_limited.load(Ordering::Relaxed));
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn statistics_test() -> anyhow::Result<()> {
+    use crate::{test, test_util, Event, Options, RawEnvelope, Value};
+
+    test::set_hook();
+
+    let mut options = Options::new();
+    options.set_before_send(|value: Value| {
+        if test_util::has_tag(&value, "discard", "true") {
+            Value::Null
+        } else {
+            value
+        }
+    });
+    options.set_transport(|_| {
+        Ok(|envelope: RawEnvelope| {
+            // ignore sessions
+            if !envelope.event().is_null() {
+                statistics().record_sent(envelope.serialize().as_bytes().len());
+            }
+        })
+    });
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    crate::set_tag("discard", "true");
+    Event::new().capture();
+
+    let statistics = shutdown.statistics();
+    assert_eq!(2, statistics.sent());
+    assert_ne!(0, statistics.bytes());
+    assert_eq!(1, statistics.dropped_by(DropReason::BeforeSend));
+    assert_eq!(1, statistics.dropped());
+    assert_eq!(0, statistics.failed());
+    assert_eq!(0, statistics.queue_depth());
+
+    shutdown.shutdown();
+
+    test::verify_panics();
+
+    Ok(())
+}
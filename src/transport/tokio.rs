@@ -0,0 +1,510 @@
+//! Built-in asynchronous HTTP transport running on a [`tokio`] runtime.
+
+use crate::{
+    statistics, Compression, DropReason, Dsn, Options, RateLimiter, RawEnvelope, Request,
+    Transport, TransportShutdown,
+};
+use ::{
+    reqwest::{Certificate, Client, Proxy, StatusCode},
+    std::{
+        collections::VecDeque,
+        convert::TryInto,
+        sync::{Arc, Condvar, Mutex},
+        time::Duration,
+    },
+    tokio::{runtime::Handle, sync::Notify},
+};
+
+/// Determines what [`TokioTransport`] does with a new envelope when its queue
+/// is full.
+#[derive(Copy, Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Overflow {
+    /// Drop the new envelope.
+    DropNewest,
+    /// Drop the oldest envelope in the queue to make room for the new one.
+    DropOldest,
+    /// Block the thread capturing the event until there is room in the queue.
+    ///
+    /// This will deadlock if events are captured from inside the runtime the
+    /// transport is running on and that runtime has no other thread available
+    /// to make progress, e.g. a current-thread runtime.
+    Block,
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Self::DropNewest
+    }
+}
+
+/// Asynchronous [`Transport`] that sends envelopes with [`reqwest`] from a
+/// single task spawned on an existing [`tokio`] runtime.
+///
+/// Envelopes are sent one after another in the order they were captured. The
+/// queue is bounded, see [`Overflow`] for the policies available when it's
+/// full. Rate limits received from the Sentry service are honored with a
+/// [`RateLimiter`]. [`Transport::flush`] and [`Transport::shutdown`] wait for
+/// the queue to be emptied until the given timeout is reached.
+///
+/// The proxy and CA certificates configured in [`Options`] are applied, see
+/// [`Options::http_config`].
+///
+/// # Examples
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// # use sentry_contrib_native::{Event, Options, TokioTransport};
+/// let runtime = tokio::runtime::Runtime::new()?;
+/// let handle = runtime.handle().clone();
+///
+/// let mut options = Options::new();
+/// options.set_dsn("https://public_key_1234@organization_1234.ingest.sentry.io/project_id_1234");
+/// options.set_transport(move |options| TokioTransport::new(&handle, options));
+/// let _shutdown = options.init()?;
+///
+/// Event::new().capture();
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct TokioTransport {
+    /// Queue shared with the sending task.
+    queue: Arc<Queue<RawEnvelope>>,
+    /// What to do when the queue is full.
+    overflow: Overflow,
+}
+
+impl TokioTransport {
+    /// Default maximum number of envelopes waiting to be sent.
+    pub const QUEUE_CAPACITY: usize = 1024;
+    /// Timeout for a single request.
+    pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Creates a new [`TokioTransport`] sending to the DSN configured in
+    /// `options`, spawning its sending task on `handle`. Uses a queue capacity
+    /// of [`TokioTransport::QUEUE_CAPACITY`] and [`Overflow::DropNewest`].
+    ///
+    /// If [`Options::debug`] is enabled, failed requests and envelopes dropped
+    /// because of rate limits are printed to stderr.
+    ///
+    /// # Errors
+    /// Fails if no valid DSN was configured or the HTTP client couldn't be
+    /// built.
+    #[allow(clippy::result_unit_err)]
+    pub fn new(handle: &Handle, options: &Options) -> Result<Self, ()> {
+        Self::with_queue(handle, Self::QUEUE_CAPACITY, Overflow::default(), options)
+    }
+
+    /// Creates a new [`TokioTransport`] like [`TokioTransport::new`], with a
+    /// queue holding at most `capacity` envelopes and the given `overflow`
+    /// policy.
+    ///
+    /// # Errors
+    /// Fails if `capacity` is zero, no valid DSN was configured or the HTTP
+    /// client couldn't be built.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_queue(
+        handle: &Handle,
+        capacity: usize,
+        overflow: Overflow,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        Self::with_config(handle, capacity, overflow, Compression::default(), options)
+    }
+
+    /// Creates a new [`TokioTransport`] like [`TokioTransport::new`],
+    /// compressing request bodies according to `compression`.
+    ///
+    /// # Errors
+    /// Fails if no valid DSN was configured or the HTTP client couldn't be
+    /// built.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_compression(
+        handle: &Handle,
+        compression: Compression,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        Self::with_config(
+            handle,
+            Self::QUEUE_CAPACITY,
+            Overflow::default(),
+            compression,
+            options,
+        )
+    }
+
+    /// Creates a new [`TokioTransport`] like [`TokioTransport::with_queue`],
+    /// compressing request bodies according to `compression`.
+    ///
+    /// # Errors
+    /// Fails if `capacity` is zero, no valid DSN was configured or the HTTP
+    /// client couldn't be built.
+    #[allow(clippy::result_unit_err)]
+    pub fn with_config(
+        handle: &Handle,
+        capacity: usize,
+        overflow: Overflow,
+        compression: Compression,
+        options: &Options,
+    ) -> Result<Self, ()> {
+        // panicking here would abort the process when called by `Options::init`
+        if capacity == 0 {
+            return Err(());
+        }
+
+        let dsn = options.dsn().and_then(|dsn| Dsn::new(dsn).ok()).ok_or(())?;
+        let debug = options.debug();
+        let client = client(&dsn, options)?;
+        let queue = Arc::new(Queue::<RawEnvelope>::new(capacity));
+
+        {
+            let queue = Arc::clone(&queue);
+
+            handle.spawn(async move {
+                let mut limiter = RateLimiter::new();
+
+                while let Some(envelope) = queue.pop().await {
+                    let serialized = match limiter.filter(envelope.serialize()) {
+                        Some(serialized) => serialized,
+                        None => {
+                            statistics().record_dropped(DropReason::RateLimited);
+
+                            if debug {
+                                eprintln!(
+                                    "[sentry-contrib-native] envelope dropped because of rate limits"
+                                );
+                            }
+
+                            continue;
+                        }
+                    };
+
+                    let request = serialized.into_compressed_request(dsn.clone(), compression);
+                    let bytes = request.body().as_bytes().len();
+
+                    if let Err(error) = send(&client, &mut limiter, request).await {
+                        if error.status() == Some(StatusCode::TOO_MANY_REQUESTS) {
+                            statistics().record_dropped(DropReason::RateLimited);
+                        } else {
+                            statistics().record_failed();
+                        }
+
+                        // only connection errors and server errors can succeed later
+                        match error.status() {
+                            Some(status) if !status.is_server_error() => (),
+                            _ => envelope.report_failure(),
+                        }
+
+                        if debug {
+                            eprintln!("[sentry-contrib-native] failed to send envelope: {}", error);
+                        }
+                    } else {
+                        statistics().record_sent(bytes);
+                    }
+                }
+
+                queue.finish();
+            });
+        }
+
+        Ok(Self { queue, overflow })
+    }
+}
+
+impl Transport for TokioTransport {
+    fn send(&self, envelope: RawEnvelope) {
+        if let Some(envelope) = self.queue.push(envelope, self.overflow) {
+            statistics().record_dropped(DropReason::QueueFull);
+            envelope.report_failure();
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        self.queue.wait_flushed(timeout)
+    }
+
+    fn shutdown(self: Box<Self>, timeout: Duration) -> TransportShutdown {
+        self.queue.close();
+
+        if self.queue.wait_finished(timeout) {
+            TransportShutdown::Success
+        } else {
+            TransportShutdown::TimedOut
+        }
+    }
+}
+
+/// Builds the [`Client`] sending to `dsn` with the proxy and CA certificates
+/// configured in `options`.
+fn client(dsn: &Dsn, options: &Options) -> Result<Client, ()> {
+    let config = options.http_config().map_err(|_| ())?;
+    let mut builder = Client::builder().timeout(TokioTransport::REQUEST_TIMEOUT);
+
+    // like `RustlsTransport`, ignore `HTTP(S)_PROXY` from the environment
+    builder = match config.proxy_for(dsn.host()) {
+        Some(proxy) => builder.proxy(Proxy::all(proxy.url().as_str()).map_err(|_| ())?),
+        None => builder.no_proxy(),
+    };
+
+    if config.ca_certs().is_some() {
+        builder = builder.tls_built_in_root_certs(false);
+
+        for cert in config.load_ca_certs().map_err(|_| ())? {
+            builder = builder.add_root_certificate(Certificate::from_der(&cert).map_err(|_| ())?);
+        }
+    }
+
+    builder.build().map_err(|_| ())
+}
+
+/// Sends `request` with `client` and updates `limiter` with the response,
+/// error status codes are returned as errors.
+async fn send(
+    client: &Client,
+    limiter: &mut RateLimiter,
+    request: Request,
+) -> Result<(), reqwest::Error> {
+    let request = request.map(|body| body.as_bytes().to_vec()).try_into()?;
+    let response = client.execute(request).await?;
+    limiter.update_from_headers(response.status().as_u16(), response.headers());
+    response.error_for_status()?;
+
+    Ok(())
+}
+
+/// Bounded queue shared between the threads capturing events and the sending
+/// task.
+#[derive(Debug)]
+struct Queue<T> {
+    /// Maximum number of items in the queue.
+    capacity: usize,
+    /// Queue state.
+    state: Mutex<State<T>>,
+    /// Wakes up the sending task when an item was pushed or the queue was
+    /// closed.
+    pushed: Notify,
+    /// Wakes up threads blocked by [`Overflow::Block`] or waiting for the
+    /// sending task to flush or finish.
+    popped: Condvar,
+}
+
+/// Mutable state of a [`Queue`].
+#[derive(Debug)]
+struct State<T> {
+    /// Items waiting to be sent.
+    items: VecDeque<T>,
+    /// No more items will be accepted, the sending task finishes after
+    /// emptying the queue.
+    closed: bool,
+    /// The sending task is still busy with the last item it popped.
+    sending: bool,
+    /// The sending task has finished.
+    finished: bool,
+}
+
+impl<T> Queue<T> {
+    /// Creates a new empty [`Queue`].
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State {
+                items: VecDeque::with_capacity(capacity),
+                closed: false,
+                sending: false,
+                finished: false,
+            }),
+            pushed: Notify::new(),
+            popped: Condvar::new(),
+        }
+    }
+
+    /// Pushes `item` to the back of the queue, applying `overflow` if it's
+    /// full. Returns the item that was dropped, if any.
+    fn push(&self, item: T, overflow: Overflow) -> Option<T> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let mut dropped = None;
+
+        if state.closed {
+            return Some(item);
+        }
+
+        if state.items.len() >= self.capacity {
+            match overflow {
+                Overflow::DropNewest => return Some(item),
+                Overflow::DropOldest => dropped = state.items.pop_front(),
+                Overflow::Block => {
+                    state = self
+                        .popped
+                        .wait_while(state, |state| {
+                            !state.closed && state.items.len() >= self.capacity
+                        })
+                        .expect("lock poisoned");
+
+                    if state.closed {
+                        return Some(item);
+                    }
+                }
+            }
+        }
+
+        state.items.push_back(item);
+        // count it before the sending task can pop it
+        statistics().record_enqueued();
+        drop(state);
+        self.pushed.notify_one();
+
+        if dropped.is_some() {
+            statistics().record_dequeued();
+        }
+
+        dropped
+    }
+
+    /// Pops the next item, waiting for one to be pushed if the queue is empty.
+    /// Returns [`None`] if the queue was closed and is empty.
+    async fn pop(&self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.state.lock().expect("lock poisoned");
+                // the sending task is done with the last item once it asks for the next one
+                let item = state.items.pop_front();
+                state.sending = item.is_some();
+                let closed = state.closed;
+                drop(state);
+                self.popped.notify_all();
+
+                if let Some(item) = item {
+                    statistics().record_dequeued();
+                    return Some(item);
+                } else if closed {
+                    return None;
+                }
+            }
+
+            // `Notify` stores a permit if nobody is waiting, so no wake up is lost
+            self.pushed.notified().await;
+        }
+    }
+
+    /// Closes the queue, no more items will be accepted.
+    fn close(&self) {
+        self.state.lock().expect("lock poisoned").closed = true;
+        self.pushed.notify_one();
+        self.popped.notify_all();
+    }
+
+    /// Signals that the sending task has finished.
+    fn finish(&self) {
+        self.state.lock().expect("lock poisoned").finished = true;
+        self.popped.notify_all();
+    }
+
+    /// Waits up to `timeout` for the queue to be empty and the sending task to
+    /// be done with the last item. Returns `false` if it timed out.
+    fn wait_flushed(&self, timeout: Duration) -> bool {
+        let (state, _) = self
+            .popped
+            .wait_timeout_while(
+                self.state.lock().expect("lock poisoned"),
+                timeout,
+                |state| !state.finished && (state.sending || !state.items.is_empty()),
+            )
+            .expect("lock poisoned");
+
+        state.finished || (!state.sending && state.items.is_empty())
+    }
+
+    /// Waits up to `timeout` for the sending task to finish. Returns `false`
+    /// if it timed out.
+    fn wait_finished(&self, timeout: Duration) -> bool {
+        let (state, _) = self
+            .popped
+            .wait_timeout_while(
+                self.state.lock().expect("lock poisoned"),
+                timeout,
+                |state| !state.finished,
+            )
+            .expect("lock poisoned");
+
+        state.finished
+    }
+}
+
+#[test]
+fn queue() {
+    use ::tokio::runtime::Builder;
+    use std::thread;
+
+    let runtime = Builder::new_current_thread().build().unwrap();
+
+    let queue = Queue::new(2);
+    assert_eq!(None, queue.push(1, Overflow::DropNewest));
+    assert_eq!(None, queue.push(2, Overflow::DropNewest));
+    assert_eq!(Some(3), queue.push(3, Overflow::DropNewest));
+    assert_eq!(Some(1), runtime.block_on(queue.pop()));
+    assert_eq!(Some(2), runtime.block_on(queue.pop()));
+
+    assert_eq!(None, queue.push(1, Overflow::DropOldest));
+    assert_eq!(None, queue.push(2, Overflow::DropOldest));
+    assert_eq!(Some(1), queue.push(3, Overflow::DropOldest));
+    assert_eq!(Some(2), runtime.block_on(queue.pop()));
+    assert_eq!(Some(3), runtime.block_on(queue.pop()));
+
+    let queue = Arc::new(queue);
+    assert_eq!(None, queue.push(1, Overflow::Block));
+    assert_eq!(None, queue.push(2, Overflow::Block));
+    let handle = {
+        let queue = Arc::clone(&queue);
+        thread::spawn(move || queue.push(3, Overflow::Block))
+    };
+    assert_eq!(Some(1), runtime.block_on(queue.pop()));
+    assert_eq!(None, handle.join().unwrap());
+    assert_eq!(Some(2), runtime.block_on(queue.pop()));
+    assert_eq!(Some(3), runtime.block_on(queue.pop()));
+
+    // the last item is in flight until the next one is requested
+    assert!(!queue.wait_flushed(Duration::from_millis(10)));
+    assert_eq!(None, queue.push(4, Overflow::Block));
+    assert!(!queue.wait_flushed(Duration::from_millis(10)));
+    assert_eq!(Some(4), runtime.block_on(queue.pop()));
+
+    queue.close();
+    assert_eq!(None, runtime.block_on(queue.pop()));
+    assert!(queue.wait_flushed(Duration::from_millis(10)));
+    assert_eq!(Some(4), queue.push(4, Overflow::Block));
+    assert_eq!(None, runtime.block_on(queue.pop()));
+
+    assert!(!queue.wait_finished(Duration::from_millis(10)));
+    queue.finish();
+    assert!(queue.wait_finished(Duration::from_millis(10)));
+}
+
+#[cfg(test)]
+#[rusty_fork::fork_test(timeout_ms = 60000)]
+fn tokio() -> anyhow::Result<()> {
+    use crate::{test, Event};
+    use ::tokio::runtime::Runtime;
+
+    test::set_hook();
+
+    let runtime = Runtime::new()?;
+    let handle = runtime.handle().clone();
+
+    let mut options = Options::new();
+    options.set_transport(move |options| TokioTransport::new(&handle, options));
+    let shutdown = options.init()?;
+
+    Event::new().capture();
+    Event::new().capture();
+    Event::new().capture();
+
+    shutdown.shutdown();
+
+    // sessions may be sent in addition to the events
+    let statistics = statistics();
+    assert!(statistics.sent() >= 3);
+    assert_eq!(0, statistics.failed());
+    assert_eq!(0, statistics.queue_depth());
+
+    test::verify_panics();
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Ordered background worker used by the shipped transports.
+
+use crate::{statistics, DropReason, RawEnvelope, TransportShutdown};
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Condvar, Mutex,
+    },
+    thread::Builder,
+    time::Duration,
+};
+
+/// Background thread that sends envelopes in the order they were enqueued.
+#[derive(Debug)]
+pub struct Worker {
+    /// Queue of envelopes waiting to be sent.
+    sender: SyncSender<RawEnvelope>,
+    /// Progress of the worker thread.
+    progress: Arc<(Mutex<Progress>, Condvar)>,
+}
+
+/// Progress of the worker thread, see [`Worker::flush`] and
+/// [`Worker::shutdown`].
+#[derive(Debug, Default)]
+struct Progress {
+    /// Number of envelopes enqueued that weren't sent yet.
+    pending: usize,
+    /// Set to `true` by the worker thread after it has emptied the queue.
+    done: bool,
+}
+
+impl Worker {
+    /// Spawns a new worker thread with the given `name`, which calls `send`
+    /// for every envelope in the queue, one after another. The queue holds at
+    /// most `capacity` envelopes. If `send` panics, the envelope is counted as
+    /// failed and the worker thread carries on with the next one.
+    ///
+    /// # Errors
+    /// Fails if the thread couldn't be spawned.
+    pub fn new<S: FnMut(RawEnvelope) + 'static + Send>(
+        name: &str,
+        capacity: usize,
+        mut send: S,
+    ) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<RawEnvelope>(capacity);
+        let progress = Arc::new((Mutex::new(Progress::default()), Condvar::new()));
+
+        {
+            let progress = Arc::clone(&progress);
+
+            Builder::new().name(name.into()).spawn(move || {
+                let (lock, cvar) = &*progress;
+
+                // only stops once the sender is dropped and the queue is empty
+                for envelope in receiver {
+                    statistics().record_dequeued();
+
+                    // a panic would otherwise leave `pending` behind forever
+                    if panic::catch_unwind(AssertUnwindSafe(|| send(envelope))).is_err() {
+                        statistics().record_failed();
+                    }
+
+                    lock.lock().expect("lock poisoned").pending -= 1;
+                    cvar.notify_all();
+                }
+
+                lock.lock().expect("lock poisoned").done = true;
+                cvar.notify_all();
+            })?;
+        }
+
+        Ok(Self { sender, progress })
+    }
+
+    /// Enqueues `envelope` without blocking. Returns `false` if the queue was
+    /// full or the worker thread is gone and the envelope was dropped, which
+    /// is reported as a failure.
+    pub fn enqueue(&self, envelope: RawEnvelope) -> bool {
+        // count it before the worker thread can dequeue it
+        statistics().record_enqueued();
+        self.progress.0.lock().expect("lock poisoned").pending += 1;
+
+        match self.sender.try_send(envelope) {
+            Ok(()) => true,
+            Err(error) => {
+                let (envelope, reason) = match error {
+                    TrySendError::Full(envelope) => (envelope, DropReason::QueueFull),
+                    TrySendError::Disconnected(envelope) => (envelope, DropReason::Other),
+                };
+
+                self.progress.0.lock().expect("lock poisoned").pending -= 1;
+                self.progress.1.notify_all();
+                statistics().record_dequeued();
+                statistics().record_dropped(reason);
+                envelope.report_failure();
+                false
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the worker thread to send all envelopes
+    /// enqueued so far, without closing the queue. Returns `false` if it timed
+    /// out.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let (lock, cvar) = &*self.progress;
+        let (progress, _) = cvar
+            .wait_timeout_while(lock.lock().expect("lock poisoned"), timeout, |progress| {
+                progress.pending > 0
+            })
+            .expect("lock poisoned");
+
+        progress.pending == 0
+    }
+
+    /// Closes the queue and waits up to `timeout` for the worker thread to
+    /// send all remaining envelopes.
+    pub fn shutdown(self, timeout: Duration) -> TransportShutdown {
+        drop(self.sender);
+
+        let (lock, cvar) = &*self.progress;
+        let (progress, _) = cvar
+            .wait_timeout_while(lock.lock().expect("lock poisoned"), timeout, |progress| {
+                !progress.done
+            })
+            .expect("lock poisoned");
+
+        if progress.done {
+            TransportShutdown::Success
+        } else {
+            TransportShutdown::TimedOut
+        }
+    }
+}
+
+#[test]
+fn worker() -> anyhow::Result<()> {
+    use crate::Envelope;
+
+    let worker = Worker::new("sentry-test-worker", 2, |_| panic!("failed to send"))?;
+
+    // the worker thread survives panics
+    assert!(worker.enqueue(RawEnvelope::from(Envelope::from(b"{}\n".to_vec()))));
+    assert!(worker.flush(Duration::from_secs(1)));
+    assert!(worker.enqueue(RawEnvelope::from(Envelope::from(b"{}\n".to_vec()))));
+    assert_eq!(
+        TransportShutdown::Success,
+        worker.shutdown(Duration::from_secs(1))
+    );
+
+    Ok(())
+}
@@ -2,6 +2,8 @@
 
 use crate::{CToR, Error, Object, RToC};
 use rmpv::decode;
+#[cfg(feature = "transport-custom")]
+use serde_json::Value as JsonValue;
 use std::{
     collections::BTreeMap,
     convert::{TryFrom, TryInto},
@@ -114,6 +116,13 @@ impl Value {
         }
     }
 
+    /// Decodes [`Value`] from `MessagePack`, like Sentry Native serializes
+    /// events, e.g. in Crashpad uploads.
+    #[cfg(feature = "mock-server")]
+    pub(crate) fn from_msgpack(mut msg: &[u8]) -> Option<Self> {
+        decode::read_value(&mut msg).ok().map(Mp::into_value)
+    }
+
     /// Yields [`sys::Value`], [`Value`] is consumed and caller is responsible
     /// for deallocating [`sys::Value`].
     ///
@@ -646,6 +655,36 @@ impl Value {
             Err(Error::TryConvert(self))
         }
     }
+
+    /// Converts [`Value`] to [`serde_json::Value`]. Non-finite
+    /// [`Value::Double`]s are converted to `null`. Whole [`Value::Double`]s
+    /// outside the [`i32`] range, which [`Json::into_value`] creates from
+    /// integers, are converted back to integers if they are exact.
+    #[cfg(feature = "transport-custom")]
+    #[allow(clippy::cast_possible_truncation, clippy::float_cmp)]
+    pub(crate) fn into_json(self) -> JsonValue {
+        match self {
+            Self::Null => JsonValue::Null,
+            Self::Bool(value) => value.into(),
+            Self::Int(value) => value.into(),
+            Self::Double(value)
+                if value.trunc() == value
+                    && value.abs() <= MAX_EXACT_INTEGER
+                    && (value < f64::from(i32::MIN) || value > f64::from(i32::MAX)) =>
+            {
+                (value as i64).into()
+            }
+            Self::Double(value) => value.into(),
+            Self::String(value) => value.into(),
+            Self::List(value) => value.into_iter().map(Self::into_json).collect(),
+            Self::Map(value) => JsonValue::Object(
+                value
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_json()))
+                    .collect(),
+            ),
+        }
+    }
 }
 
 /// Convenience trait to convert [`rmpv::Value`] to [`Value`].
@@ -689,6 +728,44 @@ impl Mp for rmpv::Value {
     }
 }
 
+/// Largest integer a [`Value::Double`] can represent exactly, 2^53.
+#[cfg(feature = "transport-custom")]
+pub(crate) const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.;
+
+/// Convenience trait to convert [`serde_json::Value`] to [`Value`].
+#[cfg(feature = "transport-custom")]
+pub trait Json {
+    /// Convert [`serde_json::Value`] to [`Value`]. Integers that don't fit
+    /// into an [`i32`] are converted to [`Value::Double`], which is only exact
+    /// up to 2^53.
+    fn into_value(self) -> Value;
+}
+
+#[cfg(feature = "transport-custom")]
+impl Json for JsonValue {
+    fn into_value(self) -> Value {
+        match self {
+            Self::Null => Value::Null,
+            Self::Bool(value) => Value::Bool(value),
+            Self::Number(value) => value
+                .as_i64()
+                .and_then(|value| value.try_into().ok())
+                .map_or_else(
+                    || Value::Double(value.as_f64().unwrap_or_default()),
+                    Value::Int,
+                ),
+            Self::String(value) => Value::String(value),
+            Self::Array(value) => Value::List(value.into_iter().map(Json::into_value).collect()),
+            Self::Object(value) => Value::Map(
+                value
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
 impl From<()> for Value {
     fn from(_: ()) -> Self {
         Self::Null
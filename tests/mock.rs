@@ -0,0 +1,67 @@
+#![warn(
+    clippy::cargo,
+    clippy::missing_docs_in_private_items,
+    clippy::nursery,
+    clippy::pedantic,
+    missing_docs
+)]
+#![cfg(all(crashpad, feature = "mock-server"))]
+
+use anyhow::Result;
+use sentry_contrib_native::{mock::Server, test_util};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+#[test]
+fn crash() -> Result<()> {
+    let server = Server::start()?;
+
+    #[cfg(not(target_os = "windows"))]
+    let example = "crash";
+    #[cfg(target_os = "windows")]
+    let example = "crash.exe";
+    let example = PathBuf::from(env!("OUT_DIR"))
+        .parent()
+        .and_then(Path::parent)
+        .and_then(Path::parent)
+        .unwrap()
+        .join("examples")
+        .join(example);
+
+    // the crashpad handler uploads directly to the mock server
+    let mut child = Command::new(example)
+        .env("SENTRY_DSN", server.dsn())
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("make sure to build the example first!");
+    let id: [u8; 16] = rand::random();
+    child.stdin.as_mut().unwrap().write_all(&id)?;
+    assert!(!child.wait()?.success());
+
+    let minidumps = server.wait_for_minidumps(1, Duration::from_secs(60));
+    assert_eq!(1, minidumps.len());
+    assert_eq!(0, server.rejected());
+
+    let minidump = &minidumps[0];
+    assert!(minidump.minidump.starts_with(b"MDMP"));
+    assert_eq!(
+        fs::read("tests/res/attachment.txt")?,
+        *minidump.attachments.get("attachment.txt").unwrap()
+    );
+
+    let event = minidump.event().unwrap();
+    assert!(test_util::has_user(&event, "id", hex::encode(id)));
+    assert!(test_util::has_tag(&event, "test-tag", "test"));
+    // breadcrumbs are uploaded separately
+    assert!(minidump
+        .attachments
+        .keys()
+        .any(|name| name.starts_with("__sentry-breadcrumb")));
+
+    Ok(())
+}
@@ -1,9 +1,9 @@
-#[cfg(feature = "transport-custom")]
+#[cfg(all(feature = "transport-custom", not(feature = "transport-rustls")))]
 pub mod custom_transport;
 pub mod event;
 
 use anyhow::{anyhow, bail, Error, Result};
-#[cfg(feature = "transport-custom")]
+#[cfg(all(feature = "transport-custom", not(feature = "transport-rustls")))]
 use custom_transport::Transport;
 use event::{Attachment, Event, MinEvent};
 use futures_util::{future, FutureExt};
@@ -268,8 +268,10 @@ async fn events_internal(
     let mut options = Options::new();
     options.set_debug(true);
     options.set_logger(|level, message| eprintln!("[{}]: {}", level, message));
-    #[cfg(feature = "transport-custom")]
+    #[cfg(all(feature = "transport-custom", not(feature = "transport-rustls")))]
     options.set_transport(Transport::new);
+    #[cfg(feature = "transport-rustls")]
+    options.set_transport(sentry::RustlsTransport::new);
 
     // apply custom configuration
     if let Some(option) = option {